@@ -0,0 +1,718 @@
+use glib::ToValue;
+use gst::prelude::*;
+use gst_video::prelude::*;
+use gstreamer_video as gst_video;
+use image::{DynamicImage, Rgba, RgbaImage};
+use parking_lot::Mutex;
+use std::convert::TryFrom;
+
+use crate::convert;
+use crate::error::PristineError;
+use crate::layout::{Layout, DEFAULT_BACKGROUND, DEFAULT_MAX_DIMENSION};
+
+/// A finished atlas texture.
+#[derive(Debug, Clone)]
+pub struct Atlas {
+    /// Position of the atlas in the stream, starting at 0.
+    pub index: u32,
+    /// Stream frame number of the first frame in the atlas.
+    pub first_frame: u32,
+    /// Number of frames the atlas holds.
+    pub frames: u32,
+    /// Layout of the texture, cropped to the used columns for the last atlas of a stream.
+    pub layout: Layout,
+    /// The texture, encoded to PNG.
+    pub png: Vec<u8>,
+    /// Timestamp of the first frame in the atlas.
+    pub pts: gst::ClockTime,
+    /// Time covered by the frames in the atlas.
+    pub duration: gst::ClockTime,
+}
+
+impl Atlas {
+    /// Wraps the PNG into a buffer timed like the frames it holds, its offsets being the
+    /// stream frame numbers of its first frame and of the frame after its last.
+    pub fn to_buffer(&self) -> gst::Buffer {
+        let mut buffer = gst::Buffer::from_mut_slice(self.png.clone());
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(self.pts);
+            buffer.set_duration(self.duration);
+            buffer.set_offset(self.first_frame as u64);
+            buffer.set_offset_end((self.first_frame + self.frames) as u64);
+        }
+        buffer
+    }
+
+    /// Reads back an atlas pushed by `pristineatlas` or `pristineenc` with the caps it was
+    /// negotiated with, so that an application can hand it to a
+    /// [`PackWriter`](crate::PackWriter).
+    ///
+    /// Every atlas but the last of a stream is full, so the caps grid cropped to the frames
+    /// of the buffer gives its layout, and its first frame gives its index.
+    pub fn from_buffer(buffer: &gst::BufferRef, caps: &gst::CapsRef) -> Option<Atlas> {
+        let structure = caps.get_structure(0)?;
+        let dimension = |field: &str| {
+            structure
+                .get_some::<i32>(field)
+                .ok()
+                .and_then(|value| u32::try_from(value).ok())
+                .filter(|&value| value > 0)
+        };
+        let grid = Layout::grid(
+            dimension("columns")?,
+            dimension("rows")?,
+            dimension("cell-width")?,
+            dimension("cell-height")?,
+            structure.get_some::<bool>("power-of-two").unwrap_or(false),
+        );
+        let first_frame = u32::try_from(buffer.get_offset()).ok()?;
+        let frames = buffer
+            .get_offset_end()
+            .checked_sub(buffer.get_offset())
+            .and_then(|frames| u32::try_from(frames).ok())
+            .filter(|&frames| frames > 0 && frames <= grid.frames())?;
+        let png = buffer.map_readable().ok()?.as_slice().to_vec();
+        Some(Atlas {
+            index: first_frame / grid.frames(),
+            first_frame,
+            frames,
+            layout: grid.crop(frames),
+            png,
+            pts: buffer.get_pts(),
+            duration: buffer.get_duration(),
+        })
+    }
+}
+
+/// Tiles raw video frames into atlases.
+///
+/// The tiler knows nothing about packs: it hands out every atlas it completes and leaves
+/// saving and registering it to the caller.
+pub struct Tiler {
+    info: gst_video::VideoInfo,
+    layout: Layout,
+    context: RgbaImage,
+    cursor: u32,
+    index: u32,
+    frames: u32,
+    pts: gst::ClockTime,
+    end: gst::ClockTime,
+    background: Option<Rgba<u8>>,
+}
+
+impl Tiler {
+    /// Creates a tiler for frames described by `info`, tiled with the given layout.
+    pub fn new(info: gst_video::VideoInfo, layout: Layout) -> Tiler {
+        Tiler {
+            info,
+            context: RgbaImage::new(layout.texture_width, layout.texture_height),
+            layout,
+            cursor: 0,
+            index: 0,
+            frames: 0,
+            pts: gst::CLOCK_TIME_NONE,
+            end: gst::CLOCK_TIME_NONE,
+            background: None,
+        }
+    }
+
+    /// Fits frames into their cell keeping their aspect ratio, filling the borders with the
+    /// given RGBA colour, instead of stretching them.
+    pub fn letterbox(mut self, background: [u8; 4]) -> Tiler {
+        self.background = Some(Rgba(background));
+        self
+    }
+
+    /// Drops the atlas being filled and starts counting frames and atlases from zero again.
+    pub fn reset(&mut self) {
+        let background = self.background;
+        *self = Tiler::new(self.info.clone(), self.layout);
+        self.background = background;
+    }
+
+    /// Layout of a full atlas.
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Format of the frames being tiled.
+    pub fn info(&self) -> &gst_video::VideoInfo {
+        &self.info
+    }
+
+    /// Number of frames tiled so far.
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /// Number of atlases completed so far.
+    pub fn atlases(&self) -> u32 {
+        self.index
+    }
+
+    /// Tiles a raw frame into the atlas, returning the atlas once it is complete.
+    pub fn push(
+        &mut self,
+        buffer: &gst::BufferRef,
+        pts: gst::ClockTime,
+        duration: gst::ClockTime,
+    ) -> Result<Option<Atlas>, PristineError> {
+        // The mapped frame honours the strides and plane offsets of any VideoMeta
+        // upstream attached, which may differ from the negotiated VideoInfo.
+        let frame = gst_video::VideoFrameRef::from_buffer_ref_readable(buffer, &self.info)
+            .map_err(|_| {
+                PristineError::Gstreamer("Failed to map input buffer readable".to_string())
+            })?;
+        let planes = (0..frame.n_planes())
+            .map(|plane| frame.plane_data(plane).unwrap_or(&[]))
+            .collect::<Vec<_>>();
+        let strides = frame
+            .plane_stride()
+            .iter()
+            .map(|&stride| stride as usize)
+            .collect::<Vec<_>>();
+        let colorimetry = self.info.colorimetry();
+        let bt709 = colorimetry.matrix() == gst_video::VideoColorMatrix::Bt709;
+        let full_range = colorimetry.range() == gst_video::VideoColorRange::Range0255;
+        let img = convert::to_rgba(
+            frame.format(),
+            frame.width(),
+            frame.height(),
+            &planes,
+            &strides,
+            bt709,
+            full_range,
+        )
+        .ok_or_else(|| {
+            image::ImageError::Parameter(image::error::ParameterError::from_kind(
+                image::error::ParameterErrorKind::DimensionMismatch,
+            ))
+        })?;
+
+        if self.cursor == 0 {
+            self.pts = pts;
+        }
+        self.end = pts + duration;
+        let (x, y) = self.layout.cell(self.cursor);
+        let (width, height) = (self.layout.cell_width, self.layout.cell_height);
+        let cell = match self.background {
+            Some(background) => letterbox(img, width, height, background),
+            None if (img.width(), img.height()) == (width, height) => img,
+            None => image::imageops::resize(
+                &img,
+                width,
+                height,
+                image::imageops::FilterType::Triangle,
+            ),
+        };
+        image::imageops::replace(&mut self.context, &cell, x, y);
+
+        self.frames += 1;
+        self.cursor += 1;
+        if self.cursor == self.layout.frames() {
+            return self.save().map(Some);
+        }
+        Ok(None)
+    }
+
+    /// Completes the atlas being filled, if it holds any frame.
+    pub fn flush(&mut self) -> Result<Option<Atlas>, PristineError> {
+        if self.cursor == 0 {
+            return Ok(None);
+        }
+        self.save().map(Some)
+    }
+
+    /// Encodes the atlas being filled to PNG.
+    ///
+    /// An atlas the stream ends partway through is cropped to the columns it uses, and the
+    /// cells of its last column still holding frames of the previous atlas are cleared.
+    fn save(&mut self) -> Result<Atlas, PristineError> {
+        let frames = self.cursor;
+        let layout = self.layout.crop(frames);
+        let mut png = vec![];
+        if layout == self.layout {
+            DynamicImage::ImageRgba8(self.context.clone())
+                .write_to(&mut png, image::ImageOutputFormat::Png)?;
+        } else {
+            let mut atlas = RgbaImage::new(layout.texture_width, layout.texture_height);
+            let used = image::imageops::crop_imm(
+                &self.context,
+                0,
+                0,
+                layout.columns * layout.cell_width,
+                layout.rows * layout.cell_height,
+            );
+            image::imageops::replace(&mut atlas, &used.to_image(), 0, 0);
+            let blank = RgbaImage::new(layout.cell_width, layout.cell_height);
+            for n in frames..layout.frames() {
+                let (x, y) = layout.cell(n);
+                image::imageops::replace(&mut atlas, &blank, x, y);
+            }
+            DynamicImage::ImageRgba8(atlas).write_to(&mut png, image::ImageOutputFormat::Png)?;
+        }
+
+        let atlas = Atlas {
+            index: self.index,
+            first_frame: self.frames - frames,
+            frames,
+            layout,
+            png,
+            pts: self.pts,
+            duration: self.end - self.pts,
+        };
+        self.index += 1;
+        self.cursor = 0;
+        Ok(atlas)
+    }
+}
+
+/// Scales `img` to fit a `width`×`height` cell keeping its aspect ratio, centred on a
+/// `background` coloured cell.
+fn letterbox(img: RgbaImage, width: u32, height: u32, background: Rgba<u8>) -> RgbaImage {
+    let scale = (width as f64 / img.width() as f64).min(height as f64 / img.height() as f64);
+    let fitted_width = ((img.width() as f64 * scale).round() as u32).max(1).min(width);
+    let fitted_height = ((img.height() as f64 * scale).round() as u32).max(1).min(height);
+    let fitted = if (img.width(), img.height()) == (fitted_width, fitted_height) {
+        img
+    } else {
+        image::imageops::resize(
+            &img,
+            fitted_width,
+            fitted_height,
+            image::imageops::FilterType::Triangle,
+        )
+    };
+    let mut cell = RgbaImage::from_pixel(width, height, background);
+    image::imageops::replace(
+        &mut cell,
+        &fitted,
+        (width - fitted_width) / 2,
+        (height - fitted_height) / 2,
+    );
+    cell
+}
+
+/// Settings of the elements tiling raw video into atlases.
+#[derive(Debug, Clone)]
+pub(crate) struct TilerSettings {
+    pub width: u32,
+    pub height: u32,
+    pub max_dimension: u32,
+    pub power_of_two: bool,
+    pub frame_width: u32,
+    pub frame_height: u32,
+    pub background: u32,
+}
+
+impl Default for TilerSettings {
+    fn default() -> Self {
+        TilerSettings {
+            width: 2,
+            height: 20,
+            max_dimension: DEFAULT_MAX_DIMENSION,
+            power_of_two: false,
+            frame_width: 0,
+            frame_height: 0,
+            background: DEFAULT_BACKGROUND,
+        }
+    }
+}
+
+impl TilerSettings {
+    /// Whether frames are letterboxed into cells of a fixed size.
+    pub fn letterboxed(&self) -> bool {
+        self.frame_width > 0 && self.frame_height > 0
+    }
+
+    /// Plans the atlas layout for frames described by `info` and creates their tiler.
+    ///
+    /// Letterboxed frames are fitted into their cell on the `background` colour, given as
+    /// `0xRRGGBBAA`, otherwise cells take the size of the frames.
+    pub fn tiler(&self, info: gst_video::VideoInfo) -> Tiler {
+        let (width, height) = if self.letterboxed() {
+            (self.frame_width, self.frame_height)
+        } else {
+            (info.width(), info.height())
+        };
+        let layout = Layout::plan(
+            width,
+            height,
+            self.width,
+            self.height,
+            self.max_dimension,
+            self.power_of_two,
+        );
+        let tiler = Tiler::new(info, layout);
+        if self.letterboxed() {
+            tiler.letterbox(self.background.to_be_bytes())
+        } else {
+            tiler
+        }
+    }
+
+    /// Sets one of the properties created by [`param_spec`], returning `false` for any
+    /// other property.
+    pub fn set_property(&mut self, name: &str, value: &glib::Value) -> bool {
+        let uint = || {
+            value
+                .get_some::<u32>()
+                .unwrap_or_else(|_| panic!("{} must be u32", name))
+        };
+        match name {
+            "width" => self.width = uint(),
+            "height" => self.height = uint(),
+            "max-dimension" => self.max_dimension = uint(),
+            "power-of-two" => {
+                self.power_of_two = value
+                    .get_some::<bool>()
+                    .expect("power-of-two must be bool")
+            }
+            "frame-width" => self.frame_width = uint(),
+            "frame-height" => self.frame_height = uint(),
+            "background" => self.background = uint(),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Reads one of the properties created by [`param_spec`].
+    pub fn get_property(&self, name: &str) -> Option<glib::Value> {
+        Some(match name {
+            "width" => self.width.to_value(),
+            "height" => self.height.to_value(),
+            "max-dimension" => self.max_dimension.to_value(),
+            "power-of-two" => self.power_of_two.to_value(),
+            "frame-width" => self.frame_width.to_value(),
+            "frame-height" => self.frame_height.to_value(),
+            "background" => self.background.to_value(),
+            _ => return None,
+        })
+    }
+}
+
+/// Creates the properties of [`TilerSettings`], which the tiling elements list as
+/// `subclass::Property("width", atlas::param_spec)`.
+pub(crate) fn param_spec(name: &str) -> glib::ParamSpec {
+    let uint = |nick, blurb, minimum, default| {
+        glib::ParamSpec::uint(
+            name,
+            nick,
+            blurb,
+            minimum,
+            std::u32::MAX,
+            default,
+            glib::ParamFlags::READWRITE,
+        )
+    };
+    match name {
+        "width" => uint("Width", "Columns of frames per atlas", 1, 2),
+        "height" => uint("Height", "Rows of frames per atlas", 1, 20),
+        "max-dimension" => uint(
+            "Max Dimension",
+            "The maximum width and height of an atlas texture",
+            1,
+            DEFAULT_MAX_DIMENSION,
+        ),
+        "power-of-two" => glib::ParamSpec::boolean(
+            name,
+            "Power Of Two",
+            "Pad atlas textures to power-of-two dimensions",
+            false,
+            glib::ParamFlags::READWRITE,
+        ),
+        "frame-width" => uint(
+            "Frame Width",
+            "Width of an atlas cell, frames being letterboxed into it, 0 for the frame width",
+            0,
+            0,
+        ),
+        "frame-height" => uint(
+            "Frame Height",
+            "Height of an atlas cell, frames being letterboxed into it, 0 for the frame height",
+            0,
+            0,
+        ),
+        "background" => uint(
+            "Background",
+            "Colour of the letterbox borders as 0xRRGGBBAA",
+            0,
+            DEFAULT_BACKGROUND,
+        ),
+        _ => unreachable!(),
+    }
+}
+
+/// The state of a tiling element: a tiler, and whatever the element does with the atlases
+/// it completes before they are pushed downstream.
+pub(crate) trait TilingState: Send {
+    fn tiler(&mut self) -> &mut Tiler;
+
+    /// Called with every completed atlas.
+    fn add_atlas(
+        &mut self,
+        _element: &gst_video::VideoEncoder,
+        _atlas: &Atlas,
+    ) -> Result<(), PristineError> {
+        Ok(())
+    }
+}
+
+impl TilingState for Tiler {
+    fn tiler(&mut self) -> &mut Tiler {
+        self
+    }
+}
+
+/// `VideoEncoderImpl::set_format` of the tiling elements: starts a new state for the
+/// negotiated format with `start`, announces its layout in a `pristine-layout` element
+/// message and sets the PNG caps of the layout on the src pad.
+pub(crate) fn set_format<S: TilingState>(
+    element: &gst_video::VideoEncoder,
+    cat: gst::DebugCategory,
+    state: &Mutex<Option<S>>,
+    codec_state: &gst_video::VideoCodecState<'static, gst_video::video_codec_state::Readable>,
+    start: impl FnOnce(gst_video::VideoInfo) -> Result<S, PristineError>,
+) -> Result<(), gst::LoggableError> {
+    let video_info = codec_state.get_info();
+    gst_info!(cat, obj: element, "Setting format {:?}", video_info);
+    let mut new_state = start(video_info).map_err(|e| {
+        element.post_error_message(&e.to_error_message());
+        gst_loggable_error!(cat, "Failed to reset state: {}", e)
+    })?;
+    let layout = new_state.tiler().layout();
+    gst_info!(cat, obj: element, "Atlas layout: {}", layout);
+    *state.lock() = Some(new_state);
+    let msg = gst::Message::new_element(layout.to_structure())
+        .src(Some(element))
+        .build();
+    element
+        .post_message(&msg)
+        .map_err(|e| gst_loggable_error!(cat, "Failed to announce layout: {}", e))?;
+
+    let negotiated = element
+        .set_output_state(caps(&layout), Some(codec_state))
+        .and_then(|output_state| element.negotiate(output_state));
+    if let Err(e) = negotiated {
+        let e = PristineError::Negotiation(format!("{:?}", e));
+        element.post_error_message(&e.to_error_message());
+        return Err(gst_loggable_error!(cat, "{}", e));
+    }
+    Ok(())
+}
+
+/// `VideoEncoderImpl::propose_allocation` of the tiling elements, before chaining up.
+pub(crate) fn propose_allocation(query: &mut gst::QueryRef) {
+    // Frames are mapped through VideoFrameRef, so upstream may use custom strides.
+    if let gst::QueryViewMut::Allocation(allocation) = query.view_mut() {
+        allocation.add_allocation_meta::<gst_video::VideoMeta>(None);
+    }
+}
+
+/// `VideoEncoderImpl::handle_frame` of the tiling elements.
+pub(crate) fn handle_frame<S: TilingState>(
+    element: &gst_video::VideoEncoder,
+    state: &Mutex<Option<S>>,
+    mut frame: gst_video::VideoCodecFrame,
+) -> Result<gst::FlowSuccess, gst::FlowError> {
+    let atlas = {
+        let mut state_guard = state.lock();
+        let state = state_guard.as_mut().ok_or(gst::FlowError::NotNegotiated)?;
+        let input_buffer = frame
+            .get_input_buffer()
+            .expect("frame without input buffer");
+        let tiled = state
+            .tiler()
+            .push(input_buffer, frame.get_pts(), frame.get_duration())
+            .and_then(|atlas| {
+                if let Some(atlas) = &atlas {
+                    state.add_atlas(element, atlas)?;
+                }
+                Ok(atlas)
+            });
+        tiled.map_err(|e| {
+            element.post_error_message(&e.to_error_message());
+            gst::FlowError::Error
+        })?
+    };
+
+    // Frames that do not complete an atlas are finished without output, the one that
+    // does carries the atlas timed from the first frame it holds.
+    if let Some(atlas) = atlas {
+        frame.set_output_buffer(atlas.to_buffer());
+        frame.set_pts(atlas.pts);
+        frame.set_duration(atlas.duration);
+    }
+    element.finish_frame(Some(frame))
+}
+
+/// `VideoEncoderImpl::finish` of the tiling elements: completes the last atlas, calls
+/// `end` once it was added and pushes it downstream.
+pub(crate) fn finish<S: TilingState>(
+    element: &gst_video::VideoEncoder,
+    state: &Mutex<Option<S>>,
+    end: impl FnOnce(&mut S) -> Result<(), PristineError>,
+) -> Result<gst::FlowSuccess, gst::FlowError> {
+    let atlas = {
+        let mut state_guard = state.lock();
+        let state = state_guard.as_mut().ok_or(gst::FlowError::NotNegotiated)?;
+        let finished = state.tiler().flush().and_then(|atlas| {
+            if let Some(atlas) = &atlas {
+                state.add_atlas(element, atlas)?;
+            }
+            end(state)?;
+            Ok(atlas)
+        });
+        finished.map_err(|e| {
+            element.post_error_message(&e.to_error_message());
+            gst::FlowError::Error
+        })?
+    };
+
+    // No frame is left to carry the last atlas, so it is pushed directly.
+    match atlas {
+        Some(atlas) => element
+            .get_static_pad("src")
+            .expect("tiling element without src pad")
+            .push(atlas.to_buffer()),
+        None => Ok(gst::FlowSuccess::Ok),
+    }
+}
+
+/// Caps of the atlases produced for `layout`, which downstream exporters can read the
+/// grid from. The last atlas of a stream may be cropped to fewer columns, see
+/// [`Atlas::from_buffer`].
+pub(crate) fn caps(layout: &Layout) -> gst::Caps {
+    gst::Caps::new_simple(
+        "image/png",
+        &[
+            ("columns", &(layout.columns as i32)),
+            ("rows", &(layout.rows as i32)),
+            ("cell-width", &(layout.cell_width as i32)),
+            ("cell-height", &(layout.cell_height as i32)),
+            ("power-of-two", &layout.power_of_two),
+        ],
+    )
+}
+
+/// Pad templates shared by the elements tiling raw video into PNG atlases.
+pub(crate) fn pad_templates() -> Vec<gst::PadTemplate> {
+    let formats = convert::FORMATS
+        .iter()
+        .map(|format| format.to_str())
+        .collect::<Vec<_>>();
+    let formats = formats
+        .iter()
+        .map(|format| format as &dyn glib::ToSendValue)
+        .collect::<Vec<_>>();
+    let sink_caps = gst::Caps::new_simple(
+        "video/x-raw",
+        &[
+            ("format", &gst::List::new(&formats)),
+            ("width", &gst::IntRange::<i32>::new(1, std::i32::MAX)),
+            ("height", &gst::IntRange::<i32>::new(1, std::i32::MAX)),
+            (
+                "framerate",
+                &gst::FractionRange::new(
+                    gst::Fraction::new(1, 1),
+                    gst::Fraction::new(std::i32::MAX, 1),
+                ),
+            ),
+        ],
+    );
+    let sink_pad_template = gst::PadTemplate::new(
+        "sink",
+        gst::PadDirection::Sink,
+        gst::PadPresence::Always,
+        &sink_caps,
+    )
+    .unwrap();
+    let src_caps = gst::Caps::new_simple("image/png", &[]);
+    let src_pad_template = gst::PadTemplate::new(
+        "src",
+        gst::PadDirection::Src,
+        gst::PadPresence::Always,
+        &src_caps,
+    )
+    .unwrap();
+    vec![sink_pad_template, src_pad_template]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::tests::{assert_rgb, padded_rgb};
+
+    fn frame(offset: usize, stride: usize) -> gst::Buffer {
+        gst::Buffer::from_mut_slice(padded_rgb(offset, stride))
+    }
+
+    /// Tiles a single frame into a one-cell atlas and checks it was not sheared.
+    fn assert_tiled(buffer: &gst::Buffer) {
+        gst::init().unwrap();
+        let info = gst_video::VideoInfo::builder(gst_video::VideoFormat::Rgb, 3, 2)
+            .build()
+            .unwrap();
+        let mut tiler = Tiler::new(info, Layout::plan(3, 2, 1, 1, 4096, false));
+        let atlas = tiler
+            .push(buffer, gst::ClockTime::from_mseconds(0), gst::ClockTime::from_mseconds(50))
+            .unwrap()
+            .expect("a one-cell atlas is complete after a frame");
+        assert_rgb(&image::load_from_memory(&atlas.png).unwrap().to_rgba8());
+    }
+
+    #[test]
+    fn push_skips_row_padding() {
+        // GStreamer rounds the 9 byte rows of a 3 pixel wide RGB frame up to 12 bytes.
+        assert_tiled(&frame(0, 12));
+    }
+
+    #[test]
+    fn push_honours_video_meta() {
+        let mut buffer = frame(8, 16);
+        gst_video::VideoMeta::add_full(
+            buffer.get_mut().unwrap(),
+            gst_video::VideoFrameFlags::empty(),
+            gst_video::VideoFormat::Rgb,
+            3,
+            2,
+            &[8],
+            &[16],
+        );
+        assert_tiled(&buffer);
+    }
+
+    #[test]
+    fn from_buffer_reads_back_the_cropped_last_atlas() {
+        gst::init().unwrap();
+        let info = gst_video::VideoInfo::builder(gst_video::VideoFormat::Rgb, 3, 2)
+            .build()
+            .unwrap();
+        let layout = Layout::plan(3, 2, 2, 2, 4096, false);
+        let mut tiler = Tiler::new(info, layout);
+        let mut atlases = vec![];
+        for n in 0..6 {
+            let pts = gst::ClockTime::from_mseconds(n * 50);
+            atlases.extend(
+                tiler
+                    .push(&frame(0, 12), pts, gst::ClockTime::from_mseconds(50))
+                    .unwrap(),
+            );
+        }
+        atlases.extend(tiler.flush().unwrap());
+
+        let caps = caps(&layout);
+        for atlas in &atlases {
+            let read = Atlas::from_buffer(&atlas.to_buffer(), &caps).unwrap();
+            assert_eq!(
+                (read.index, read.first_frame, read.frames, read.layout),
+                (atlas.index, atlas.first_frame, atlas.frames, atlas.layout)
+            );
+            assert_eq!((read.pts, read.duration), (atlas.pts, atlas.duration));
+            assert_eq!(read.png, atlas.png);
+        }
+        assert_eq!(atlases.len(), 2);
+        assert_eq!(atlases[1].layout.columns, 1);
+    }
+}
@@ -0,0 +1,185 @@
+use glib::subclass;
+use glib::subclass::prelude::*;
+use gst::subclass::prelude::*;
+use gst_video::prelude::*;
+use gst_video::subclass::prelude::*;
+use gstreamer_video as gst_video;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+use crate::atlas::{self, Tiler, TilerSettings};
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "pristineatlas",
+        gst::DebugColorFlags::empty(),
+        Some("Pristine atlas compositor"),
+    )
+});
+
+static PROPERTIES: [subclass::Property; 9] = [
+    subclass::Property("width", atlas::param_spec),
+    subclass::Property("height", atlas::param_spec),
+    subclass::Property("max-dimension", atlas::param_spec),
+    subclass::Property("power-of-two", atlas::param_spec),
+    subclass::Property("frame-width", atlas::param_spec),
+    subclass::Property("frame-height", atlas::param_spec),
+    subclass::Property("background", atlas::param_spec),
+    subclass::Property("frames", |name| {
+        glib::ParamSpec::uint(
+            name,
+            "Frames",
+            "The number of frames tiled so far",
+            0,
+            std::u32::MAX,
+            0,
+            glib::ParamFlags::READABLE,
+        )
+    }),
+    subclass::Property("atlases", |name| {
+        glib::ParamSpec::uint(
+            name,
+            "Atlases",
+            "The number of atlases completed so far",
+            0,
+            std::u32::MAX,
+            0,
+            glib::ParamFlags::READABLE,
+        )
+    }),
+];
+
+/// Tiles raw video frames into PNG atlases and pushes them downstream, leaving it to
+/// other elements to store them.
+pub struct Compositor {
+    state: Mutex<Option<Tiler>>,
+    settings: Mutex<TilerSettings>,
+    // Frames and atlases of the last stream, once the element has stopped.
+    stats: Mutex<(u32, u32)>,
+}
+
+impl ObjectSubclass for Compositor {
+    const NAME: &'static str = "PristineAtlas";
+    type ParentType = gst_video::VideoEncoder;
+    type Instance = gst::subclass::ElementInstanceStruct<Self>;
+    type Class = subclass::simple::ClassStruct<Self>;
+
+    glib_object_subclass!();
+
+    fn class_init(klass: &mut subclass::simple::ClassStruct<Self>) {
+        klass.set_metadata(
+            "Pristine Atlas",
+            "Encoder/Video",
+            "Tiles video frames into PNG texture atlases",
+            "CAIMEO",
+        );
+        for template in atlas::pad_templates() {
+            klass.add_pad_template(template);
+        }
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn new() -> Self {
+        Self {
+            state: Mutex::new(None),
+            settings: Mutex::new(Default::default()),
+            stats: Mutex::new((0, 0)),
+        }
+    }
+}
+
+impl ObjectImpl for Compositor {
+    fn set_property(&self, _obj: &glib::Object, id: usize, value: &glib::Value) {
+        let prop = &PROPERTIES[id];
+
+        if !self.settings.lock().set_property(prop.0, value) {
+            unreachable!()
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: usize) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id];
+
+        if let Some(value) = self.settings.lock().get_property(prop.0) {
+            return Ok(value);
+        }
+
+        match *prop {
+            subclass::Property("frames", ..) => Ok(self.stats().0.to_value()),
+            subclass::Property("atlases", ..) => Ok(self.stats().1.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl Compositor {
+    /// Frames and atlases of the running stream, or of the last one once stopped.
+    fn stats(&self) -> (u32, u32) {
+        match &*self.state.lock() {
+            Some(tiler) => (tiler.frames(), tiler.atlases()),
+            None => *self.stats.lock(),
+        }
+    }
+}
+
+impl ElementImpl for Compositor {}
+
+impl VideoEncoderImpl for Compositor {
+    fn stop(&self, _element: &gst_video::VideoEncoder) -> Result<(), gst::ErrorMessage> {
+        if let Some(tiler) = self.state.lock().take() {
+            *self.stats.lock() = (tiler.frames(), tiler.atlases());
+        }
+        Ok(())
+    }
+
+    fn flush(&self, _element: &gst_video::VideoEncoder) -> bool {
+        if let Some(tiler) = self.state.lock().as_mut() {
+            tiler.reset();
+        }
+        true
+    }
+
+    fn finish(
+        &self,
+        element: &gst_video::VideoEncoder,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        atlas::finish(element, &self.state, |_| Ok(()))
+    }
+
+    fn set_format(
+        &self,
+        element: &gst_video::VideoEncoder,
+        state: &gst_video::VideoCodecState<'static, gst_video::video_codec_state::Readable>,
+    ) -> Result<(), gst::LoggableError> {
+        let settings = self.settings.lock().clone();
+        atlas::set_format(element, *CAT, &self.state, state, |video_info| {
+            Ok(settings.tiler(video_info))
+        })
+    }
+
+    fn propose_allocation(
+        &self,
+        element: &gst_video::VideoEncoder,
+        query: &mut gst::QueryRef,
+    ) -> Result<(), gst::ErrorMessage> {
+        atlas::propose_allocation(query);
+        self.parent_propose_allocation(element, query)
+    }
+
+    fn handle_frame(
+        &self,
+        element: &gst_video::VideoEncoder,
+        frame: gst_video::VideoCodecFrame,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        atlas::handle_frame(element, &self.state, frame)
+    }
+}
+
+pub fn register(plugin: Option<&gst::Plugin>) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        plugin,
+        "pristineatlas",
+        gst::Rank::None,
+        Compositor::get_type(),
+    )
+}
@@ -0,0 +1,181 @@
+use gstreamer_video as gst_video;
+use gst_video::VideoFormat;
+use image::RgbaImage;
+
+/// Raw formats the encoder accepts on its sink pad.
+pub const FORMATS: [VideoFormat; 11] = [
+    VideoFormat::Rgba,
+    VideoFormat::Rgbx,
+    VideoFormat::Bgra,
+    VideoFormat::Bgrx,
+    VideoFormat::Rgb,
+    VideoFormat::Bgr,
+    VideoFormat::Gray8,
+    VideoFormat::I420,
+    VideoFormat::Yv12,
+    VideoFormat::Nv12,
+    VideoFormat::Yuy2,
+];
+
+/// Converts a raw video frame into a tightly packed RGBA image.
+///
+/// `planes` and `strides` hold the data and row stride of each plane of the mapped frame.
+/// YUV formats are read with BT.709 or BT.601 coefficients, in full or limited range.
+/// Returns `None` for unsupported formats or planes too short for the frame.
+pub fn to_rgba(
+    format: VideoFormat,
+    width: u32,
+    height: u32,
+    planes: &[&[u8]],
+    strides: &[usize],
+    bt709: bool,
+    full_range: bool,
+) -> Option<RgbaImage> {
+    let (w, h) = (width as usize, height as usize);
+    let mut out = vec![0u8; w * h * 4];
+    for y in 0..h {
+        for x in 0..w {
+            let pixel = match format {
+                VideoFormat::Rgba => packed(planes, strides, x, y, 4, [0, 1, 2], Some(3))?,
+                VideoFormat::Rgbx => packed(planes, strides, x, y, 4, [0, 1, 2], None)?,
+                VideoFormat::Bgra => packed(planes, strides, x, y, 4, [2, 1, 0], Some(3))?,
+                VideoFormat::Bgrx => packed(planes, strides, x, y, 4, [2, 1, 0], None)?,
+                VideoFormat::Rgb => packed(planes, strides, x, y, 3, [0, 1, 2], None)?,
+                VideoFormat::Bgr => packed(planes, strides, x, y, 3, [2, 1, 0], None)?,
+                VideoFormat::Gray8 => {
+                    let l = *planes.get(0)?.get(y * strides.get(0)? + x)?;
+                    [l, l, l, 255]
+                }
+                VideoFormat::I420 | VideoFormat::Yv12 => {
+                    // YV12 stores V before U, which GStreamer reports as swapped planes.
+                    let (u_plane, v_plane) = if format == VideoFormat::I420 {
+                        (1, 2)
+                    } else {
+                        (2, 1)
+                    };
+                    let luma = *planes.get(0)?.get(y * strides.get(0)? + x)?;
+                    let u = *planes
+                        .get(u_plane)?
+                        .get((y / 2) * strides.get(u_plane)? + x / 2)?;
+                    let v = *planes
+                        .get(v_plane)?
+                        .get((y / 2) * strides.get(v_plane)? + x / 2)?;
+                    yuv(luma, u, v, bt709, full_range)
+                }
+                VideoFormat::Nv12 => {
+                    let luma = *planes.get(0)?.get(y * strides.get(0)? + x)?;
+                    let chroma = (y / 2) * strides.get(1)? + (x / 2) * 2;
+                    let uv = planes.get(1)?.get(chroma..chroma + 2)?;
+                    yuv(luma, uv[0], uv[1], bt709, full_range)
+                }
+                VideoFormat::Yuy2 => {
+                    let pair = y * strides.get(0)? + (x / 2) * 4;
+                    let yuyv = planes.get(0)?.get(pair..pair + 4)?;
+                    yuv(yuyv[(x % 2) * 2], yuyv[1], yuyv[3], bt709, full_range)
+                }
+                _ => return None,
+            };
+            out[(y * w + x) * 4..(y * w + x) * 4 + 4].copy_from_slice(&pixel);
+        }
+    }
+    RgbaImage::from_raw(width, height, out)
+}
+
+/// Reads a pixel of a single-plane packed RGB format, `rgb` and `alpha` giving the byte
+/// offsets of the channels.
+fn packed(
+    planes: &[&[u8]],
+    strides: &[usize],
+    x: usize,
+    y: usize,
+    bytes: usize,
+    rgb: [usize; 3],
+    alpha: Option<usize>,
+) -> Option<[u8; 4]> {
+    let start = y * strides.get(0)? + x * bytes;
+    let pixel = planes.get(0)?.get(start..start + bytes)?;
+    Some([
+        pixel[rgb[0]],
+        pixel[rgb[1]],
+        pixel[rgb[2]],
+        alpha.map_or(255, |a| pixel[a]),
+    ])
+}
+
+fn yuv(y: u8, u: u8, v: u8, bt709: bool, full_range: bool) -> [u8; 4] {
+    let d = u as f32 - 128.0;
+    let e = v as f32 - 128.0;
+    let (r, g, b) = if full_range {
+        let c = y as f32;
+        if bt709 {
+            (c + 1.575 * e, c - 0.187 * d - 0.468 * e, c + 1.856 * d)
+        } else {
+            (c + 1.402 * e, c - 0.344 * d - 0.714 * e, c + 1.772 * d)
+        }
+    } else {
+        let c = (y as f32 - 16.0) * 1.164;
+        if bt709 {
+            (c + 1.793 * e, c - 0.213 * d - 0.533 * e, c + 2.112 * d)
+        } else {
+            (c + 1.596 * e, c - 0.392 * d - 0.813 * e, c + 2.017 * d)
+        }
+    };
+    let clamp = |x: f32| x.round().max(0.0).min(255.0) as u8;
+    [clamp(r), clamp(g), clamp(b), 255]
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Pixel (`x`, `y`) of the frames built by [`padded_rgb`].
+    pub(crate) fn rgb_pixel(x: u32, y: u32) -> [u8; 4] {
+        [(x * 10 + y * 100) as u8, x as u8, y as u8, 255]
+    }
+
+    /// A 3×2 RGB frame whose rows start `offset` bytes into the data and are `stride` bytes
+    /// apart, padding being filled with white.
+    pub(crate) fn padded_rgb(offset: usize, stride: usize) -> Vec<u8> {
+        let mut data = vec![0xffu8; offset + stride * 2];
+        for y in 0..2 {
+            for x in 0..3 {
+                let i = offset + y * stride + x * 3;
+                data[i..i + 3].copy_from_slice(&rgb_pixel(x as u32, y as u32)[..3]);
+            }
+        }
+        data
+    }
+
+    /// Checks that an image holds the frame built by [`padded_rgb`], without shearing.
+    pub(crate) fn assert_rgb(image: &RgbaImage) {
+        for y in 0..2 {
+            for x in 0..3 {
+                assert_eq!(image.get_pixel(x, y).0, rgb_pixel(x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn to_rgba_skips_row_padding() {
+        // A 3 pixel wide RGB frame, its 9 byte rows padded to 12 bytes.
+        let data = padded_rgb(0, 12);
+        assert_rgb(&to_rgba(VideoFormat::Rgb, 3, 2, &[&data], &[12], false, false).unwrap());
+    }
+
+    #[test]
+    fn to_rgba_reads_padded_yuv_planes() {
+        // A 3 pixel wide grey I420 frame, each plane's rows padded to 4 bytes.
+        let luma = [16, 126, 235, 0xff, 235, 126, 16, 0xff];
+        let chroma = [128, 128, 0xff, 0xff];
+        let planes: [&[u8]; 3] = [&luma, &chroma, &chroma];
+        let limited = to_rgba(VideoFormat::I420, 3, 2, &planes, &[4, 4, 4], false, false).unwrap();
+        let levels = |image: &RgbaImage, y: u32| {
+            (0..3).map(|x| image.get_pixel(x, y).0[0]).collect::<Vec<_>>()
+        };
+        assert_eq!(levels(&limited, 0), [0, 128, 255]);
+        assert_eq!(levels(&limited, 1), [255, 128, 0]);
+
+        let full = to_rgba(VideoFormat::I420, 3, 2, &planes, &[4, 4, 4], false, true).unwrap();
+        assert_eq!(levels(&full, 0), [16, 126, 235]);
+    }
+}
@@ -0,0 +1,1259 @@
+use gst::prelude::*;
+use gstreamer as gst;
+use gstreamer_app as gst_app;
+use parking_lot::Mutex;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::error::PristineError;
+use crate::layout::{Layout, DEFAULT_MAX_DIMENSION};
+use crate::pack::{self, Anchor, AtlasComplete, PackWriter};
+use crate::process;
+use crate::source::{self, Source};
+use crate::subtitles::{self, Cue};
+
+/// Summary of a finished encoding run.
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// Number of atlas textures written.
+    pub atlases: u32,
+    /// Number of video frames tiled into the atlases.
+    pub frames: u32,
+    /// Root directory of the generated pack.
+    pub pack: PathBuf,
+    /// Paths of the written atlas textures.
+    pub textures: Vec<PathBuf>,
+    /// Wall-clock time spent encoding.
+    pub elapsed: Duration,
+    /// Namespaces of the clips of a playlist, in order, empty for a single video.
+    pub clips: Vec<String>,
+}
+
+/// How often [`Encoder::run`] reports progress.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How far an encoding run has got.
+#[derive(Debug, Clone)]
+pub struct Progress {
+    /// Position of the pipeline in the source, if known.
+    pub position: Option<Duration>,
+    /// Duration of the source, `None` when it cannot be queried.
+    pub duration: Option<Duration>,
+    /// Number of video frames tiled so far.
+    pub frames: u32,
+    /// Number of atlases completed so far.
+    pub atlases: u32,
+    /// Wall-clock time spent encoding the clip so far.
+    pub elapsed: Duration,
+    /// Position of the clip being encoded in the playlist, starting at 0.
+    pub clip: usize,
+    /// Number of clips in the playlist, 1 for a single video.
+    pub clips: usize,
+}
+
+impl Progress {
+    /// Fraction of the source encoded so far, between 0 and 1, if the duration is known.
+    pub fn fraction(&self) -> Option<f64> {
+        match (self.position, self.duration) {
+            (Some(position), Some(duration)) if duration > Duration::from_secs(0) => {
+                Some((position.as_secs_f64() / duration.as_secs_f64()).min(1.0))
+            }
+            _ => None,
+        }
+    }
+
+    /// Estimated time left, extrapolated from the speed so far.
+    pub fn eta(&self) -> Option<Duration> {
+        let fraction = self.fraction()?;
+        if fraction <= 0.0 {
+            return None;
+        }
+        Some(self.elapsed.mul_f64((1.0 - fraction) / fraction))
+    }
+
+    /// Frames tiled per second of wall-clock time.
+    pub fn fps(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            self.frames as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+/// A closure the encoder calls back from [`Encoder::run`].
+#[derive(Clone)]
+struct Callback<T>(Arc<dyn Fn(&T) + Send + Sync>);
+
+impl<T> fmt::Debug for Callback<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Callback")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EncoderBuilder {
+    sources: Vec<PathBuf>,
+    name: String,
+    description: String,
+    fps: u32,
+    spi: u32,
+    scale: f64,
+    size: Option<(f64, f64)>,
+    looping: bool,
+    fcm: String,
+    output: PathBuf,
+    audio: bool,
+    audio_segment: u32,
+    max_dimension: u32,
+    power_of_two: bool,
+    frame_size: Option<(u32, u32)>,
+    max_frame_dimension: Option<u32>,
+    background: [u8; 4],
+    start: Option<Duration>,
+    end: Option<Duration>,
+    subtitles: Option<PathBuf>,
+    embedded_subtitles: bool,
+    caption: String,
+    tick: bool,
+    anchor: Anchor,
+    on_atlas: Option<Callback<AtlasComplete>>,
+    on_layout: Option<Callback<Layout>>,
+    on_progress: Option<Callback<Progress>>,
+}
+
+impl Default for EncoderBuilder {
+    fn default() -> Self {
+        EncoderBuilder {
+            sources: vec![],
+            name: "pristine".to_string(),
+            description: "Pristine Video Pack.Powered by CAIMEO. LICENSE MIT.".to_string(),
+            fps: 20,
+            spi: 1,
+            scale: 1.0,
+            size: None,
+            looping: false,
+            fcm: "lookat_xyz".to_string(),
+            output: PathBuf::from("."),
+            audio: true,
+            audio_segment: 0,
+            max_dimension: DEFAULT_MAX_DIMENSION,
+            power_of_two: false,
+            frame_size: None,
+            max_frame_dimension: None,
+            background: [0, 0, 0, 255],
+            start: None,
+            end: None,
+            subtitles: None,
+            embedded_subtitles: false,
+            caption: "actionbar".to_string(),
+            tick: false,
+            anchor: Anchor::default(),
+            on_atlas: None,
+            on_layout: None,
+            on_progress: None,
+        }
+    }
+}
+
+impl EncoderBuilder {
+    /// Sets the path of the video, of an animated GIF or APNG, or of a directory or glob
+    /// pattern of images played one per frame.
+    pub fn source<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.sources = vec![path.as_ref().to_path_buf()];
+        self
+    }
+
+    /// Packs several sources into one add-on, each clip getting a particle namespace and
+    /// a scoreboard objective named after its file, and a `play_<clip>` function.
+    pub fn playlist<I, P>(mut self, paths: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        self.sources = paths
+            .into_iter()
+            .map(|path| path.as_ref().to_path_buf())
+            .collect();
+        self
+    }
+
+    /// Sets the pack's name.
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    /// Sets the pack's description.
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = description.to_string();
+        self
+    }
+
+    /// Sets frames per second. The source is resampled to this rate, which is also the rate
+    /// the particles play back at.
+    pub fn fps(mut self, fps: u32) -> Self {
+        self.fps = fps;
+        self
+    }
+
+    /// Sets seconds per image.
+    pub fn spi(mut self, spi: u32) -> Self {
+        self.spi = spi;
+        self
+    }
+
+    /// Sets the particle scale, applied to a billboard sized after the video's aspect ratio.
+    pub fn scale(mut self, scale: f64) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    /// Sets the particle billboard size in world units, overriding the scale.
+    pub fn size(mut self, width: f64, height: f64) -> Self {
+        self.size = Some((width, height));
+        self
+    }
+
+    /// Sets looping animation.
+    pub fn looping(mut self, looping: bool) -> Self {
+        self.looping = looping;
+        self
+    }
+
+    /// Sets the facing camera mode of the particle.
+    pub fn facing_camera_mode(mut self, fcm: &str) -> Self {
+        self.fcm = fcm.to_string();
+        self
+    }
+
+    /// Sets the directory the pack is written into.
+    pub fn output<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.output = path.as_ref().to_path_buf();
+        self
+    }
+
+    /// Exports the audio track, if the source has one.
+    pub fn audio(mut self, audio: bool) -> Self {
+        self.audio = audio;
+        self
+    }
+
+    /// Splits the audio track into segments of the given length in seconds, which are
+    /// re-synced with the video at every boundary. 0 keeps a single track.
+    pub fn audio_segment(mut self, seconds: u32) -> Self {
+        self.audio_segment = seconds;
+        self
+    }
+
+    /// Sets the maximum width and height of an atlas texture.
+    pub fn max_dimension(mut self, max_dimension: u32) -> Self {
+        self.max_dimension = max_dimension;
+        self
+    }
+
+    /// Pads atlas textures to power-of-two dimensions.
+    pub fn power_of_two(mut self, power_of_two: bool) -> Self {
+        self.power_of_two = power_of_two;
+        self
+    }
+
+    /// Scales frames to fit `width`×`height` keeping their aspect ratio, letterboxing them
+    /// so that every atlas cell has that size.
+    pub fn frame_size(mut self, width: u32, height: u32) -> Self {
+        self.frame_size = Some((width, height));
+        self
+    }
+
+    /// Downscales frames keeping their aspect ratio until neither dimension exceeds
+    /// `max_frame_dimension`.
+    pub fn max_frame_dimension(mut self, max_frame_dimension: u32) -> Self {
+        self.max_frame_dimension = Some(max_frame_dimension);
+        self
+    }
+
+    /// Sets the RGBA colour of the letterbox borders, transparent with an alpha of 0.
+    pub fn background(mut self, rgba: [u8; 4]) -> Self {
+        self.background = rgba;
+        self
+    }
+
+    /// Starts encoding at the given position in the source, or in every clip of a playlist.
+    pub fn start(mut self, start: Duration) -> Self {
+        self.start = Some(start);
+        self
+    }
+
+    /// Stops encoding at the given position in the source, or in every clip of a playlist.
+    pub fn end(mut self, end: Duration) -> Self {
+        self.end = Some(end);
+        self
+    }
+
+    /// Shows the cues of an SRT or WebVTT file as in-game captions. Only a single video can
+    /// be given a subtitle file.
+    pub fn subtitles<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.subtitles = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Shows the first subtitle track of the video as in-game captions, unless a subtitle
+    /// file is given.
+    pub fn embedded_subtitles(mut self, embedded: bool) -> Self {
+        self.embedded_subtitles = embedded;
+        self
+    }
+
+    /// Sets where captions are shown: `actionbar` or `subtitle`.
+    pub fn caption_mode(mut self, mode: &str) -> Self {
+        self.caption = mode.to_string();
+        self
+    }
+
+    /// Runs the playback loop from the behaviour pack's `tick.json` while a video plays,
+    /// so that no repeating command block is needed.
+    pub fn tick(mut self, tick: bool) -> Self {
+        self.tick = tick;
+        self
+    }
+
+    /// Sets what the video is emitted from, by default the entities tagged
+    /// `pristine_screen`.
+    pub fn anchor(mut self, anchor: Anchor) -> Self {
+        self.anchor = anchor;
+        self
+    }
+
+    /// Calls `f` for every atlas added to the pack.
+    pub fn on_atlas<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&AtlasComplete) + Send + Sync + 'static,
+    {
+        self.on_atlas = Some(Callback(Arc::new(f)));
+        self
+    }
+
+    /// Calls `f` with the atlas layout of every clip, once it is planned and before its
+    /// first frame is tiled.
+    pub fn on_layout<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Layout) + Send + Sync + 'static,
+    {
+        self.on_layout = Some(Callback(Arc::new(f)));
+        self
+    }
+
+    /// Calls `f` with the progress of the run a few times per second.
+    pub fn on_progress<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Progress) + Send + Sync + 'static,
+    {
+        self.on_progress = Some(Callback(Arc::new(f)));
+        self
+    }
+
+    pub fn build(mut self) -> Result<Encoder, PristineError> {
+        if self.sources.is_empty() {
+            return Err(PristineError::Config("Missing video source".to_string()));
+        }
+        if self.sources.len() > 1 && self.subtitles.is_some() {
+            return Err(PristineError::Config(
+                "A subtitle file cannot be shared by the clips of a playlist".to_string(),
+            ));
+        }
+        // Comparisons with NaN are false, so positive lengths are checked as such.
+        let positive = |length: f64| length.is_finite() && length > 0.0;
+        if self.fps == 0 || self.spi == 0 || !positive(self.scale) || self.max_dimension == 0 {
+            return Err(PristineError::Config(
+                "fps, spi, scale and max dimension must be greater than zero".to_string(),
+            ));
+        }
+        if let Some((width, height)) = self.size {
+            if !positive(width) || !positive(height) {
+                return Err(PristineError::Config(
+                    "The billboard size must be finite and greater than zero".to_string(),
+                ));
+            }
+        }
+        if self.frame_size.map_or(false, |(width, height)| width == 0 || height == 0)
+            || self.max_frame_dimension == Some(0)
+        {
+            return Err(PristineError::Config(
+                "The frame size must be greater than zero".to_string(),
+            ));
+        }
+        if self.caption != "actionbar" && self.caption != "subtitle" {
+            return Err(PristineError::Config(format!(
+                "Unknown caption mode {}, expected actionbar or subtitle",
+                self.caption
+            )));
+        }
+        if let (Some(start), Some(end)) = (self.start, self.end) {
+            if end <= start {
+                return Err(PristineError::Config(
+                    "The end must come after the start".to_string(),
+                ));
+            }
+        }
+        let sources = self
+            .sources
+            .iter()
+            .map(|path| Source::detect(path))
+            .collect::<Result<Vec<_>, _>>()?;
+        // Pack writers change the working directory while the audio files get opened.
+        self.output = pack::absolute(&self.output)?;
+        Ok(Encoder {
+            clips: if sources.len() > 1 {
+                namespaces(&self.sources, &self.name)
+            } else {
+                vec![]
+            },
+            sources,
+            config: self,
+        })
+    }
+}
+
+/// Pads of a trimmed clip held back until the pipeline has seeked to the start of the
+/// range, so that neither the encoder nor the audio sink sees the frames decoded before.
+#[derive(Clone)]
+struct Hold(Arc<Mutex<Option<Vec<(gst::Pad, gst::PadProbeId)>>>>);
+
+impl Hold {
+    fn new() -> Hold {
+        Hold(Arc::new(Mutex::new(Some(vec![]))))
+    }
+
+    /// Blocks the data flowing through `pad` until the hold is released. Pads linked once
+    /// it was released are left alone.
+    fn block(&self, pad: &gst::Pad) {
+        if let Some(pads) = self.0.lock().as_mut() {
+            if let Some(id) =
+                pad.add_probe(gst::PadProbeType::BLOCK_DOWNSTREAM, |_, _| gst::PadProbeReturn::Ok)
+            {
+                pads.push((pad.clone(), id));
+            }
+        }
+    }
+
+    /// Lets the data through again. Whatever was blocked before a flushing seek is dropped
+    /// by the flush.
+    fn release(&self) {
+        for (pad, id) in self.0.lock().take().unwrap_or_default() {
+            pad.remove_probe(id);
+        }
+    }
+}
+
+/// A temporary file, removed once dropped.
+struct TempFile(PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Encodes a video, or a playlist of videos sharing one pack.
+#[derive(Debug, Clone)]
+pub struct Encoder {
+    sources: Vec<Source>,
+    clips: Vec<String>,
+    config: EncoderBuilder,
+}
+
+/// A source being encoded into the pack.
+struct Clip<'a> {
+    source: &'a Source,
+    /// Namespace of a playlist clip, `None` for a single video.
+    namespace: Option<&'a str>,
+    index: usize,
+}
+
+impl Clip<'_> {
+    /// Name the clip's particles, objective and sounds are prefixed with.
+    fn name<'a>(&'a self, config: &'a EncoderBuilder) -> &'a str {
+        self.namespace.unwrap_or(&config.name)
+    }
+}
+
+impl Encoder {
+    pub fn builder() -> EncoderBuilder {
+        EncoderBuilder::default()
+    }
+
+    /// Builds `… ! videoconvert ! videoscale ! videorate ! pristineenc ! fakesink`, fed by
+    /// `filesrc ! decodebin` for videos or by an `appsrc` for image sources.
+    ///
+    /// `videoscale` resizes frames to the requested size once the video's dimensions are
+    /// known, keeping their aspect ratio. The encoder letterboxes them into fixed cells.
+    ///
+    /// `videorate` drops or duplicates frames so that the encoder receives exactly `fps`
+    /// frames per second of video, which is the rate the particles are played back at.
+    ///
+    /// Every clip of a playlist runs through a pipeline of its own, only the first one
+    /// writing the pack manifests. The decoded pads of a trimmed clip are blocked by `hold`
+    /// until it has seeked.
+    fn build_pipeline(
+        &self,
+        clip: &Clip,
+        subtitles: Option<&Path>,
+        hold: Option<&Hold>,
+    ) -> Result<(gst::Pipeline, gst::Element), PristineError> {
+        let pipeline = gst::Pipeline::new(None);
+        let convert = gst::ElementFactory::make("videoconvert", None)?;
+        let scale = gst::ElementFactory::make("videoscale", None)?;
+        let size = gst::ElementFactory::make("capsfilter", None)?;
+        let rate = gst::ElementFactory::make("videorate", None)?;
+        let filter = gst::ElementFactory::make("capsfilter", None)?;
+        let enc = gst::ElementFactory::make("pristineenc", Some("enc"))?;
+        let sink = gst::ElementFactory::make("fakesink", None)?;
+
+        filter.set_property(
+            "caps",
+            &gst::Caps::builder("video/x-raw")
+                .field("framerate", &gst::Fraction::new(self.config.fps as i32, 1))
+                .build(),
+        )?;
+        enc.set_property("width", &self.config.spi)?;
+        enc.set_property("height", &self.config.fps)?;
+        enc.set_property("max-dimension", &self.config.max_dimension)?;
+        enc.set_property("power-of-two", &self.config.power_of_two)?;
+        enc.set_property("scale", &self.config.scale)?;
+        if let Some((width, height)) = self.config.frame_size {
+            enc.set_property("frame-width", &width)?;
+            enc.set_property("frame-height", &height)?;
+        }
+        enc.set_property("background", &u32::from_be_bytes(self.config.background))?;
+        if let Some((width, height)) = self.config.size {
+            enc.set_property("billboard-width", &width)?;
+            enc.set_property("billboard-height", &height)?;
+        }
+        enc.set_property("loop", &self.config.looping)?;
+        enc.set_property("pack-name", &self.config.name)?;
+        enc.set_property("description", &self.config.description)?;
+        enc.set_property("facing-camera-mode", &self.config.fcm)?;
+        enc.set_property("output", &self.config.output.to_string_lossy().into_owned())?;
+        if let Some(subtitles) = subtitles {
+            enc.set_property("subtitles", &subtitles.to_string_lossy().into_owned())?;
+            let offset = self.config.start.unwrap_or_default().as_millis() as u64;
+            enc.set_property("subtitle-offset", &offset)?;
+        }
+        enc.set_property("caption-mode", &self.config.caption)?;
+        if let Some(namespace) = clip.namespace {
+            enc.set_property("namespace", &namespace)?;
+        }
+        enc.set_property("init-pack", &(clip.index == 0))?;
+        enc.set_property("tick", &self.config.tick)?;
+        enc.set_property("anchor", &self.config.anchor.to_string())?;
+
+        // The encoder only outputs a buffer per atlas, too late for the sink to preroll.
+        sink.set_property("async", &false)?;
+
+        pipeline.add_many(&[&convert, &scale, &size, &rate, &filter, &enc, &sink])?;
+        gst::Element::link_many(&[&convert, &scale, &size, &rate, &filter, &enc, &sink])?;
+
+        match clip.source {
+            Source::Media(path) => self.link_decoder(
+                &pipeline,
+                path,
+                clip.name(&self.config),
+                &convert,
+                &size,
+                &enc,
+                hold.cloned(),
+            )?,
+            source => self.link_images(&pipeline, source, &convert, &size)?,
+        }
+        Ok((pipeline, enc))
+    }
+
+    /// Fixed cells are filled exactly, a maximum dimension only ever downscales.
+    fn bounds(&self) -> Option<((u32, u32), bool)> {
+        match (self.config.frame_size, self.config.max_frame_dimension) {
+            (Some(frame_size), _) => Some((frame_size, true)),
+            (None, Some(max)) => Some(((max, max), false)),
+            (None, None) => None,
+        }
+    }
+
+    /// Adds `filesrc ! decodebin`, linking its video pad once it shows up and adding an audio
+    /// branch for its audio pad.
+    fn link_decoder(
+        &self,
+        pipeline: &gst::Pipeline,
+        path: &Path,
+        name: &str,
+        convert: &gst::Element,
+        size: &gst::Element,
+        enc: &gst::Element,
+        hold: Option<Hold>,
+    ) -> Result<(), PristineError> {
+        let src = gst::ElementFactory::make("filesrc", None)?;
+        let decodebin = gst::ElementFactory::make("decodebin", None)?;
+        src.set_property("location", &path.to_string_lossy().into_owned())?;
+        pipeline.add_many(&[&src, &decodebin])?;
+        src.link(&decodebin)?;
+
+        let sounds = self
+            .config
+            .output
+            .join(&self.config.name)
+            .join("resource_pack")
+            .join("sounds");
+        let name = name.to_string();
+        let audio = self.config.audio;
+        let audio_segment = self.config.audio_segment;
+        let bounds = self.bounds();
+        let pipeline_weak = pipeline.downgrade();
+        let convert_weak = convert.downgrade();
+        let enc_weak = enc.downgrade();
+        let size_weak = size.downgrade();
+        decodebin.connect_pad_added(move |dbin, src_pad| {
+            let (pipeline, convert, enc) =
+                match (pipeline_weak.upgrade(), convert_weak.upgrade(), enc_weak.upgrade()) {
+                    (Some(pipeline), Some(convert), Some(enc)) => (pipeline, convert, enc),
+                    _ => return,
+                };
+            let caps = src_pad.get_current_caps();
+            let structure = caps.as_ref().and_then(|caps| caps.get_structure(0));
+            let media = structure
+                .map(|s| s.get_name().to_string())
+                .unwrap_or_default();
+
+            if media.starts_with("video/") {
+                let sink_pad = convert
+                    .get_static_pad("sink")
+                    .expect("videoconvert without sink pad");
+                if sink_pad.is_linked() {
+                    return;
+                }
+                if let (Some(bounds), Some(s), Some(size)) = (bounds, structure, size_weak.upgrade())
+                {
+                    let width = s.get_some::<i32>("width").unwrap_or(1).max(1) as u32;
+                    let height = s.get_some::<i32>("height").unwrap_or(1).max(1) as u32;
+                    let par = s
+                        .get_some::<gst::Fraction>("pixel-aspect-ratio")
+                        .unwrap_or_else(|_| gst::Fraction::new(1, 1));
+                    if let Err(e) = resize(&size, width, height, par, bounds) {
+                        dbin.post_error_message(&e.to_error_message());
+                        return;
+                    }
+                }
+                if let Some(hold) = &hold {
+                    hold.block(&sink_pad);
+                }
+                if let Err(e) = src_pad.link(&sink_pad) {
+                    gst_element_error!(
+                        dbin,
+                        gst::CoreError::Negotiation,
+                        ["Failed to link decodebin to videoconvert: {:?}", e]
+                    );
+                }
+            } else if media.starts_with("audio/") && audio {
+                // Only the first audio track is exported.
+                let linked = enc.get_property("audio").ok().and_then(|v| v.get_some().ok());
+                if linked == Some(true) {
+                    return;
+                }
+                let hold = hold.as_ref();
+                match link_audio(&pipeline, src_pad, &sounds, &name, audio_segment, hold) {
+                    Ok(()) => {
+                        let _ = enc.set_property("audio", &true);
+                    }
+                    Err(e) => dbin.post_error_message(&e.to_error_message()),
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Adds an `appsrc` pushing the frames of an image source as RGBA video, decoded on a
+    /// thread of its own.
+    ///
+    /// Image sources cannot seek, so the trimmed range is applied while pushing and the
+    /// frames are retimed to start at zero.
+    fn link_images(
+        &self,
+        pipeline: &gst::Pipeline,
+        source: &Source,
+        convert: &gst::Element,
+        size: &gst::Element,
+    ) -> Result<(), PristineError> {
+        let appsrc = gst::ElementFactory::make("appsrc", None)?
+            .dynamic_cast::<gst_app::AppSrc>()
+            .map_err(|_| PristineError::Gstreamer("appsrc is not an AppSrc".to_string()))?;
+        appsrc.set_property_format(gst::Format::Time);
+        appsrc.set_property("block", &true)?;
+        pipeline.add(&appsrc)?;
+        appsrc.link(convert)?;
+
+        let source = source.clone();
+        let fps = self.config.fps;
+        let bounds = self.bounds();
+        let start = self.config.start.unwrap_or_default();
+        let end = self.config.end;
+        let size = size.clone();
+        std::thread::spawn(move || {
+            let pushed = (|| -> Result<(), PristineError> {
+                let mut position = Duration::default();
+                let mut dimensions = None;
+                for frame in source::frames(&source, fps)? {
+                    let frame = frame?;
+                    let (begin, finish) = (position, position + frame.duration);
+                    position = finish;
+                    if finish <= start {
+                        continue;
+                    }
+                    if end.map_or(false, |end| begin >= end) {
+                        break;
+                    }
+                    let finish = end.map_or(finish, |end| finish.min(end));
+                    let begin = begin.max(start);
+
+                    let (width, height) = *dimensions.get_or_insert_with(|| {
+                        (frame.image.width(), frame.image.height())
+                    });
+                    let image = if (frame.image.width(), frame.image.height()) == (width, height) {
+                        frame.image
+                    } else {
+                        image::imageops::resize(
+                            &frame.image,
+                            width,
+                            height,
+                            image::imageops::FilterType::Triangle,
+                        )
+                    };
+                    // The first frame pushed decides the caps of the whole stream.
+                    if appsrc.get_caps().is_none() {
+                        if let Some(bounds) = bounds {
+                            resize(&size, width, height, gst::Fraction::new(1, 1), bounds)?;
+                        }
+                        appsrc.set_caps(Some(
+                            &gst::Caps::builder("video/x-raw")
+                                .field("format", &"RGBA")
+                                .field("width", &(width as i32))
+                                .field("height", &(height as i32))
+                                .field("pixel-aspect-ratio", &gst::Fraction::new(1, 1))
+                                .field("framerate", &gst::Fraction::new(0, 1))
+                                .build(),
+                        ));
+                    }
+
+                    let mut buffer = gst::Buffer::from_mut_slice(image.into_raw());
+                    {
+                        let buffer = buffer.get_mut().unwrap();
+                        buffer.set_pts(clock_time(begin - start));
+                        buffer.set_duration(clock_time(finish - begin));
+                    }
+                    // Pushing only fails once the pipeline is shutting down.
+                    if appsrc.push_buffer(buffer).is_err() {
+                        return Ok(());
+                    }
+                }
+                Ok(())
+            })();
+            match pushed {
+                Ok(()) => {
+                    let _ = appsrc.end_of_stream();
+                }
+                Err(e) => appsrc.post_error_message(&e.to_error_message()),
+            }
+        });
+        Ok(())
+    }
+
+    /// Collects the cues of the first text subtitle track of the video into a temporary SRT
+    /// file, or returns `None` when the video has no such track.
+    ///
+    /// This runs `filesrc ! parsebin` to the end before encoding, which is quick as nothing
+    /// but the subtitles gets decoded. SubStation Alpha tracks are reduced to their text,
+    /// bitmap subtitles are skipped with a warning.
+    fn extract_subtitles(
+        &self,
+        path: &Path,
+        name: &str,
+    ) -> Result<Option<TempFile>, PristineError> {
+        let pipeline = gst::Pipeline::new(None);
+        let src = gst::ElementFactory::make("filesrc", None)?;
+        let parsebin = gst::ElementFactory::make("parsebin", None)?;
+        src.set_property("location", &path.to_string_lossy().into_owned())?;
+        pipeline.add_many(&[&src, &parsebin])?;
+        src.link(&parsebin)?;
+
+        let cues = Arc::new(Mutex::new(vec![]));
+        let pipeline_weak = pipeline.downgrade();
+        let collected = cues.clone();
+        parsebin.connect_pad_added(move |parsebin, src_pad| {
+            let pipeline = match pipeline_weak.upgrade() {
+                Some(pipeline) => pipeline,
+                None => return,
+            };
+            let media = src_pad
+                .get_current_caps()
+                .and_then(|caps| caps.get_structure(0).map(|s| s.get_name().to_string()))
+                .unwrap_or_default();
+            // Only the first subtitle track is collected, everything else is dropped. MP4
+            // tx3g tracks already come out of qtdemux as text/x-raw.
+            let collecting = pipeline.get_by_name("subtitles").is_some();
+            let branch = match media.as_str() {
+                "text/x-raw" if !collecting => {
+                    subtitle_sink(collected.clone()).map(|sink| vec![sink])
+                }
+                "application/x-ssa" | "application/x-ass" if !collecting => {
+                    gst::ElementFactory::make("ssaparse", None)
+                        .map_err(PristineError::from)
+                        .and_then(|parse| Ok(vec![parse, subtitle_sink(collected.clone())?]))
+                }
+                _ => {
+                    let subtitles = media.starts_with("subpicture/")
+                        || media.starts_with("text/")
+                        || media.starts_with("application/x-subtitle");
+                    if subtitles && !collecting {
+                        gst_element_warning!(
+                            parsebin,
+                            gst::StreamError::CodecNotFound,
+                            ["Skipping {} subtitles, only text tracks can be shown", media]
+                        );
+                    }
+                    gst::ElementFactory::make("fakesink", None)
+                        .map(|sink| vec![sink])
+                        .map_err(PristineError::from)
+                }
+            };
+            let linked = branch.and_then(|branch| {
+                let sink = branch.last().expect("empty subtitle branch");
+                sink.set_property("sync", &false)?;
+                let elements = branch.iter().collect::<Vec<_>>();
+                pipeline.add_many(&elements)?;
+                gst::Element::link_many(&elements)?;
+                for element in &elements {
+                    element.sync_state_with_parent()?;
+                }
+                let sink_pad = branch[0].get_static_pad("sink").expect("element without sink pad");
+                src_pad.link(&sink_pad).map_err(|e| {
+                    PristineError::Negotiation(format!("Failed to link the subtitles: {:?}", e))
+                })?;
+                Ok(())
+            });
+            if let Err(e) = linked {
+                parsebin.post_error_message(&e.to_error_message());
+            }
+        });
+
+        pipeline.set_state(gst::State::Playing)?;
+        let bus = pipeline
+            .get_bus()
+            .ok_or_else(|| PristineError::Gstreamer("Pipeline without bus".to_string()))?;
+        let mut result = Ok(());
+        for msg in bus.iter_timed(gst::CLOCK_TIME_NONE) {
+            use gst::MessageView;
+            match msg.view() {
+                MessageView::Error(err) => {
+                    result = Err(PristineError::Pipeline {
+                        source: err.get_src().map(|s| s.get_path_string().to_string()),
+                        error: err.get_error(),
+                        debug: err.get_debug(),
+                    });
+                    break;
+                }
+                MessageView::Eos(..) => break,
+                _ => (),
+            }
+        }
+        pipeline.set_state(gst::State::Null)?;
+        result?;
+
+        let cues = cues.lock();
+        if cues.is_empty() {
+            return Ok(None);
+        }
+        // Named after the process and the time, so that concurrent runs do not share it.
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let srt = TempFile(std::env::temp_dir().join(format!(
+            "{}-{}-{}.srt",
+            name,
+            std::process::id(),
+            nanos
+        )));
+        subtitles::write_srt(&cues, &srt.0)?;
+        Ok(Some(srt))
+    }
+
+    /// Prerolls the pipeline and seeks to the requested range, so that only the frames in
+    /// it reach the encoder.
+    ///
+    /// Pausing completes once decodebin has exposed and linked its pads, whose data `hold`
+    /// keeps back until the seek was sent.
+    fn seek(&self, pipeline: &gst::Pipeline, hold: &Hold) -> Result<(), PristineError> {
+        pipeline.set_state(gst::State::Paused)?;
+        let (result, _, _) = pipeline.get_state(gst::CLOCK_TIME_NONE);
+        result?;
+
+        let stop_type = if self.config.end.is_some() {
+            gst::SeekType::Set
+        } else {
+            gst::SeekType::None
+        };
+        pipeline.seek(
+            1.0,
+            gst::SeekFlags::FLUSH | gst::SeekFlags::ACCURATE,
+            gst::SeekType::Set,
+            clock_time(self.config.start.unwrap_or_default()),
+            stop_type,
+            self.config.end.map_or(gst::CLOCK_TIME_NONE, clock_time),
+        )?;
+        hold.release();
+        Ok(())
+    }
+
+    /// Runs the pipeline of every clip to completion and reports what was written.
+    pub fn run(&self) -> Result<Report, PristineError> {
+        gst::init()?;
+        process::register(None)?;
+        let started = Instant::now();
+        let mut report = Report {
+            atlases: 0,
+            frames: 0,
+            pack: self.config.output.join(&self.config.name),
+            textures: vec![],
+            elapsed: Duration::default(),
+            clips: self.clips.clone(),
+        };
+        for (index, source) in self.sources.iter().enumerate() {
+            let clip = Clip {
+                source,
+                namespace: self.clips.get(index).map(String::as_str),
+                index,
+            };
+            let (frames, atlases, textures) = self.run_clip(&clip)?;
+            report.frames += frames;
+            report.atlases += atlases;
+            report.textures.extend(textures);
+        }
+        if !self.clips.is_empty() {
+            PackWriter::write_playlist(
+                &self.config.output,
+                &self.config.name,
+                &self.config.description,
+                &self.clips,
+                self.config.tick,
+                &self.config.anchor,
+            )?;
+        }
+        report.elapsed = started.elapsed();
+        Ok(report)
+    }
+
+    /// Runs the pipeline of a clip to completion, returning the frames, atlases and
+    /// textures it added to the pack. Progress is reported for the clip alone.
+    fn run_clip(&self, clip: &Clip) -> Result<(u32, u32, Vec<PathBuf>), PristineError> {
+        let started = Instant::now();
+        // Extracted cues are removed once the clip is encoded, or failed to.
+        let extracted = match (&self.config.subtitles, clip.source) {
+            (None, Source::Media(path)) if self.config.embedded_subtitles => {
+                self.extract_subtitles(path, clip.name(&self.config))?
+            }
+            _ => None,
+        };
+        let subtitles = self
+            .config
+            .subtitles
+            .clone()
+            .or_else(|| extracted.as_ref().map(|srt| srt.0.clone()));
+        let trimmed = self.config.start.is_some() || self.config.end.is_some();
+        let hold = match clip.source {
+            Source::Media(_) if trimmed => Some(Hold::new()),
+            _ => None,
+        };
+        let (pipeline, enc) = self.build_pipeline(clip, subtitles.as_deref(), hold.as_ref())?;
+        if let Some(hold) = &hold {
+            self.seek(&pipeline, hold)?;
+        }
+        pipeline.set_state(gst::State::Playing)?;
+
+        let bus = pipeline
+            .get_bus()
+            .ok_or_else(|| PristineError::Gstreamer("Pipeline without bus".to_string()))?;
+        let mut result = Ok(());
+        let mut reported = Instant::now();
+        loop {
+            if let Some(Callback(f)) = &self.config.on_progress {
+                if reported.elapsed() >= PROGRESS_INTERVAL {
+                    // Progress is relative to the trimmed range. Image sources are already
+                    // retimed to start at zero, only the position of a video is offset.
+                    let start = self.config.start.unwrap_or_default();
+                    let offset = match clip.source {
+                        Source::Media(_) => start,
+                        _ => Duration::default(),
+                    };
+                    let end = pipeline
+                        .query_duration::<gst::ClockTime>()
+                        .and_then(duration)
+                        .map(|d| self.config.end.map_or(d, |end| end.min(d)))
+                        .or(self.config.end);
+                    f(&Progress {
+                        position: pipeline
+                            .query_position::<gst::ClockTime>()
+                            .and_then(duration)
+                            .map(|position| position.checked_sub(offset).unwrap_or_default()),
+                        duration: end.map(|end| end.checked_sub(start).unwrap_or_default()),
+                        frames: counter(&enc, "frames").unwrap_or(0),
+                        atlases: counter(&enc, "atlases").unwrap_or(0),
+                        elapsed: started.elapsed(),
+                        clip: clip.index,
+                        clips: self.sources.len(),
+                    });
+                    reported = Instant::now();
+                }
+            }
+
+            let msg = match bus.timed_pop(gst::ClockTime::from_mseconds(
+                PROGRESS_INTERVAL.as_millis() as u64,
+            )) {
+                Some(msg) => msg,
+                None => continue,
+            };
+            use gst::MessageView;
+            match msg.view() {
+                MessageView::Error(err) => {
+                    result = Err(PristineError::Pipeline {
+                        source: err.get_src().map(|s| s.get_path_string().to_string()),
+                        error: err.get_error(),
+                        debug: err.get_debug(),
+                    });
+                    break;
+                }
+                MessageView::Eos(..) => break,
+                MessageView::Element(..) => {
+                    if let (Some(atlas), Some(Callback(f))) =
+                        (AtlasComplete::from_message(&msg), &self.config.on_atlas)
+                    {
+                        f(&atlas);
+                    }
+                    if let (Some(layout), Some(Callback(f))) =
+                        (Layout::from_message(&msg), &self.config.on_layout)
+                    {
+                        f(&layout);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        pipeline.set_state(gst::State::Null)?;
+        result?;
+
+        let textures = enc
+            .get_property("textures")?
+            .get::<Vec<String>>()
+            .map_err(|e| PristineError::Gstreamer(e.to_string()))?
+            .unwrap_or_default();
+        Ok((
+            counter(&enc, "frames")?,
+            counter(&enc, "atlases")?,
+            textures.into_iter().map(PathBuf::from).collect(),
+        ))
+    }
+}
+
+/// Functions of the playlist itself, which the `<clip>_<function>` functions of a clip
+/// named after one of them could overwrite, or be overwritten by.
+const PLAYLIST_FUNCTIONS: [&str; 9] = [
+    "play", "select", "setup", "loop", "stop", "pause", "resume", "tick", "seek",
+];
+
+/// Namespaces of playlist clips: their file names reduced to lowercase letters, digits and
+/// underscores, numbered when several clips end up with the same one or with the name of
+/// the pack, whose objective selects the clip. Names starting like a playlist function are
+/// prefixed with `clip_`.
+fn namespaces(paths: &[PathBuf], name: &str) -> Vec<String> {
+    let mut namespaces = vec![name.to_string()];
+    for path in paths {
+        let stem = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let mut namespace = stem
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect::<String>();
+        if namespace.trim_matches('_').is_empty() {
+            namespace = "clip".to_string();
+        }
+        let reserved = PLAYLIST_FUNCTIONS.iter().any(|function| {
+            namespace == *function || namespace.starts_with(&format!("{}_", function))
+        });
+        if reserved {
+            namespace = format!("clip_{}", namespace);
+        }
+        let base = namespace.clone();
+        let mut n = 1;
+        while namespaces.contains(&namespace) {
+            n += 1;
+            namespace = format!("{}_{}", base, n);
+        }
+        namespaces.push(namespace);
+    }
+    namespaces.split_off(1)
+}
+
+/// Size of a `width`×`height` frame with the given pixel aspect ratio, in square pixels,
+/// scaled to fit `bounds`.
+fn fit(
+    width: u32,
+    height: u32,
+    par: gst::Fraction,
+    bounds: (u32, u32),
+    upscale: bool,
+) -> (u32, u32) {
+    let display_width = width as f64 * *par.numer() as f64 / (*par.denom()).max(1) as f64;
+    let mut factor = (bounds.0 as f64 / display_width).min(bounds.1 as f64 / height as f64);
+    if !upscale {
+        factor = factor.min(1.0);
+    }
+    (
+        ((display_width * factor).round() as u32).max(1),
+        ((height as f64 * factor).round() as u32).max(1),
+    )
+}
+
+/// Makes the `size` caps filter scale `width`×`height` frames to fit `bounds`, upscaling
+/// them if asked to.
+fn resize(
+    size: &gst::Element,
+    width: u32,
+    height: u32,
+    par: gst::Fraction,
+    (bounds, upscale): ((u32, u32), bool),
+) -> Result<(), PristineError> {
+    let (width, height) = fit(width, height, par, bounds, upscale);
+    let caps = gst::Caps::builder("video/x-raw")
+        .field("width", &(width as i32))
+        .field("height", &(height as i32))
+        .field("pixel-aspect-ratio", &gst::Fraction::new(1, 1))
+        .build();
+    size.set_property("caps", &caps)?;
+    Ok(())
+}
+
+/// An `appsink` named `subtitles` collecting the text buffers it receives as cues.
+fn subtitle_sink(cues: Arc<Mutex<Vec<Cue>>>) -> Result<gst::Element, PristineError> {
+    let sink = gst::ElementFactory::make("appsink", Some("subtitles"))?;
+    let appsink = sink
+        .clone()
+        .dynamic_cast::<gst_app::AppSink>()
+        .map_err(|_| PristineError::Gstreamer("appsink is not an AppSink".to_string()))?;
+    appsink.set_callbacks(
+        gst_app::AppSinkCallbacks::builder()
+            .new_sample(move |appsink| {
+                let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+                let buffer = sample.get_buffer().ok_or(gst::FlowError::Error)?;
+                let map = buffer.map_readable().map_err(|_| gst::FlowError::Error)?;
+                let start = duration(buffer.get_pts());
+                let end = start.and_then(|start| {
+                    duration(buffer.get_duration()).map(|length| start + length)
+                });
+                // The text may be Pango markup, whose tags are stripped.
+                let text = String::from_utf8_lossy(map.as_slice());
+                if let (Some(start), Some(end)) = (start, end) {
+                    cues.lock().extend(subtitles::cue(start, end, &text));
+                }
+                Ok(gst::FlowSuccess::Ok)
+            })
+            .build(),
+    );
+    Ok(sink)
+}
+
+/// Reads one of the `u32` counters of the encoder.
+fn counter(enc: &gst::Element, name: &str) -> Result<u32, PristineError> {
+    enc.get_property(name)?
+        .get_some::<u32>()
+        .map_err(|e| PristineError::Gstreamer(e.to_string()))
+}
+
+fn duration(time: gst::ClockTime) -> Option<Duration> {
+    time.nseconds().map(Duration::from_nanos)
+}
+
+fn clock_time(duration: Duration) -> gst::ClockTime {
+    gst::ClockTime::from_nseconds(duration.as_nanos() as u64)
+}
+
+/// Encodes the audio pad of `decodebin` to Ogg Vorbis next to the pack's textures, either as
+/// `{name}.ogg` or as `{name}_{i}.ogg` segments of `segment` seconds.
+fn link_audio(
+    pipeline: &gst::Pipeline,
+    src_pad: &gst::Pad,
+    sounds: &Path,
+    name: &str,
+    segment: u32,
+    hold: Option<&Hold>,
+) -> Result<(), PristineError> {
+    std::fs::create_dir_all(sounds)?;
+
+    let convert = gst::ElementFactory::make("audioconvert", None)?;
+    let resample = gst::ElementFactory::make("audioresample", None)?;
+    let vorbis = gst::ElementFactory::make("vorbisenc", None)?;
+    let mut branch = vec![convert, resample, vorbis];
+    if segment == 0 {
+        let mux = gst::ElementFactory::make("oggmux", None)?;
+        let sink = gst::ElementFactory::make("filesink", None)?;
+        let location = sounds.join(format!("{}.ogg", name));
+        sink.set_property("location", &location.to_string_lossy().into_owned())?;
+        // A trimmed clip holds the audio back until it has seeked, so the sink cannot wait
+        // for it to preroll. splitmuxsink already runs its sinks this way.
+        sink.set_property("async", &false)?;
+        branch.push(mux);
+        branch.push(sink);
+    } else {
+        let sink = gst::ElementFactory::make("splitmuxsink", None)?;
+        let location = sounds.join(format!("{}_%d.ogg", name));
+        sink.set_property("location", &location.to_string_lossy().into_owned())?;
+        sink.set_property("max-size-time", &(segment as u64 * gst::SECOND_VAL))?;
+        sink.set_property("muxer", &gst::ElementFactory::make("oggmux", None)?)?;
+        branch.push(sink);
+    }
+
+    let elements: Vec<&gst::Element> = branch.iter().collect();
+    pipeline.add_many(&elements)?;
+    gst::Element::link_many(&elements)?;
+    for element in &elements {
+        element.sync_state_with_parent()?;
+    }
+
+    let sink_pad = branch[0]
+        .get_static_pad("sink")
+        .expect("audioconvert without sink pad");
+    if let Some(hold) = hold {
+        hold.block(&sink_pad);
+    }
+    src_pad.link(&sink_pad).map_err(|e| {
+        PristineError::Negotiation(format!("Failed to link the audio track: {:?}", e))
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn namespaces_avoid_playlist_functions() {
+        let paths = ["play.mp4", "loop.mp4", "play_x.mp4", "intro.mp4", "intro.gif"]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            namespaces(&paths, "intro"),
+            ["clip_play", "clip_loop", "clip_play_x", "intro_2", "intro_3"]
+        );
+    }
+}
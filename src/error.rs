@@ -0,0 +1,140 @@
+use gstreamer as gst;
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong while encoding a pack.
+#[derive(Debug)]
+pub enum PristineError {
+    /// Invalid options passed to the encoder.
+    Config(String),
+    /// Reading the source or writing the pack failed.
+    Io(io::Error),
+    /// A frame or an atlas could not be converted or encoded.
+    Image(image::ImageError),
+    /// The pack itself could not be generated.
+    Pack(String),
+    /// The encoder could not agree on a format with its peers.
+    Negotiation(String),
+    /// GStreamer could not be initialised or the pipeline could not be built.
+    Gstreamer(String),
+    /// An element reported an error on the pipeline bus.
+    Pipeline {
+        source: Option<String>,
+        error: glib::Error,
+        debug: Option<String>,
+    },
+}
+
+impl PristineError {
+    /// Process exit code the CLI reports for this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            PristineError::Config(_) => 2,
+            PristineError::Io(_) => 3,
+            PristineError::Image(_) => 4,
+            PristineError::Pack(_) => 5,
+            PristineError::Negotiation(_) => 6,
+            PristineError::Gstreamer(_) => 7,
+            PristineError::Pipeline { error, .. } => {
+                if error.kind::<gst::ResourceError>().is_some() {
+                    3
+                } else if let Some(gst::StreamError::Encode) = error.kind::<gst::StreamError>() {
+                    4
+                } else if error.kind::<gst::LibraryError>().is_some() {
+                    5
+                } else if let Some(gst::CoreError::Negotiation) = error.kind::<gst::CoreError>() {
+                    6
+                } else {
+                    7
+                }
+            }
+        }
+    }
+
+    /// Converts the error into a message an element can post on the bus.
+    pub(crate) fn to_error_message(&self) -> gst::ErrorMessage {
+        match self {
+            PristineError::Io(_) => gst_error_msg!(gst::ResourceError::Write, ["{}", self]),
+            PristineError::Image(_) => gst_error_msg!(gst::StreamError::Encode, ["{}", self]),
+            PristineError::Pack(_) => gst_error_msg!(gst::LibraryError::Failed, ["{}", self]),
+            PristineError::Negotiation(_) => {
+                gst_error_msg!(gst::CoreError::Negotiation, ["{}", self])
+            }
+            PristineError::Config(_)
+            | PristineError::Gstreamer(_)
+            | PristineError::Pipeline { .. } => {
+                gst_error_msg!(gst::CoreError::Failed, ["{}", self])
+            }
+        }
+    }
+}
+
+impl fmt::Display for PristineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PristineError::Config(e) => write!(f, "Invalid configuration: {}", e),
+            PristineError::Io(e) => write!(f, "I/O error: {}", e),
+            PristineError::Image(e) => write!(f, "Image error: {}", e),
+            PristineError::Pack(e) => write!(f, "Unable to generate pack: {}", e),
+            PristineError::Negotiation(e) => write!(f, "Negotiation failed: {}", e),
+            PristineError::Gstreamer(e) => write!(f, "GStreamer error: {}", e),
+            PristineError::Pipeline {
+                source,
+                error,
+                debug,
+            } => {
+                write!(
+                    f,
+                    "Error received from element {}: {}",
+                    source.as_deref().unwrap_or("<unknown>"),
+                    error
+                )?;
+                if let Some(debug) = debug {
+                    write!(f, " ({})", debug)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for PristineError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PristineError::Io(e) => Some(e),
+            PristineError::Image(e) => Some(e),
+            PristineError::Pipeline { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PristineError {
+    fn from(e: io::Error) -> Self {
+        PristineError::Io(e)
+    }
+}
+
+impl From<image::ImageError> for PristineError {
+    fn from(e: image::ImageError) -> Self {
+        PristineError::Image(e)
+    }
+}
+
+impl From<glib::Error> for PristineError {
+    fn from(e: glib::Error) -> Self {
+        PristineError::Gstreamer(e.to_string())
+    }
+}
+
+impl From<glib::BoolError> for PristineError {
+    fn from(e: glib::BoolError) -> Self {
+        PristineError::Gstreamer(e.to_string())
+    }
+}
+
+impl From<gst::StateChangeError> for PristineError {
+    fn from(e: gst::StateChangeError) -> Self {
+        PristineError::Gstreamer(e.to_string())
+    }
+}
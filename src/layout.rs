@@ -0,0 +1,162 @@
+use std::fmt;
+
+/// Largest texture dimension Bedrock loads without downsampling.
+pub const DEFAULT_MAX_DIMENSION: u32 = 4096;
+/// Opaque black, as `0xRRGGBBAA`.
+pub const DEFAULT_BACKGROUND: u32 = 0x0000_00ff;
+
+/// Name of the element message posted once the layout of a stream is planned.
+const LAYOUT: &str = "pristine-layout";
+
+/// How frames are tiled into an atlas texture.
+///
+/// Frames fill the atlas column by column, each cell holding one frame downscaled to
+/// `cell_width`×`cell_height`. The texture may be larger than the grid when it is padded
+/// to power-of-two dimensions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub columns: u32,
+    pub rows: u32,
+    pub cell_width: u32,
+    pub cell_height: u32,
+    pub texture_width: u32,
+    pub texture_height: u32,
+    pub power_of_two: bool,
+}
+
+impl Layout {
+    /// Plans the atlas for `frame_width`×`frame_height` frames, starting from the requested
+    /// `columns`×`rows` grid.
+    ///
+    /// The grid shrinks until the texture fits into `max_dimension`. Frames are only
+    /// downscaled when a single one does not fit. With `power_of_two` the texture is padded
+    /// to power-of-two dimensions no larger than `max_dimension`.
+    pub fn plan(
+        frame_width: u32,
+        frame_height: u32,
+        columns: u32,
+        rows: u32,
+        max_dimension: u32,
+        power_of_two: bool,
+    ) -> Layout {
+        let limit = if power_of_two {
+            previous_power_of_two(max_dimension.max(1))
+        } else {
+            max_dimension.max(1)
+        };
+
+        let factor = (limit as f64 / frame_width.max(1) as f64)
+            .min(limit as f64 / frame_height.max(1) as f64)
+            .min(1.0);
+        let cell_width = ((frame_width as f64 * factor).floor() as u32).max(1);
+        let cell_height = ((frame_height as f64 * factor).floor() as u32).max(1);
+
+        let columns = columns.max(1).min(limit / cell_width).max(1);
+        let rows = rows.max(1).min(limit / cell_height).max(1);
+
+        Layout::grid(columns, rows, cell_width, cell_height, power_of_two)
+    }
+
+    /// Layout of a `columns`×`rows` grid of cells of the given size.
+    pub(crate) fn grid(
+        columns: u32,
+        rows: u32,
+        cell_width: u32,
+        cell_height: u32,
+        power_of_two: bool,
+    ) -> Layout {
+        let (mut texture_width, mut texture_height) = (columns * cell_width, rows * cell_height);
+        if power_of_two {
+            texture_width = texture_width.next_power_of_two();
+            texture_height = texture_height.next_power_of_two();
+        }
+
+        Layout {
+            columns,
+            rows,
+            cell_width,
+            cell_height,
+            texture_width,
+            texture_height,
+            power_of_two,
+        }
+    }
+
+    /// Layout of an atlas holding only the first `frames` frames, cropped to the columns
+    /// and rows they use.
+    pub fn crop(&self, frames: u32) -> Layout {
+        let frames = frames.max(1).min(self.frames());
+        Layout::grid(
+            (frames + self.rows - 1) / self.rows,
+            frames.min(self.rows),
+            self.cell_width,
+            self.cell_height,
+            self.power_of_two,
+        )
+    }
+
+    /// Number of frames a full atlas holds.
+    pub fn frames(&self) -> u32 {
+        self.columns * self.rows
+    }
+
+    /// Top-left corner of the cell holding the `n`th frame of an atlas.
+    pub fn cell(&self, n: u32) -> (u32, u32) {
+        (
+            (n / self.rows) * self.cell_width,
+            (n % self.rows) * self.cell_height,
+        )
+    }
+
+    /// Reads the layout back from the `pristine-layout` message the tiling elements post
+    /// when the format is set, `None` for any other message.
+    pub fn from_message(msg: &gst::Message) -> Option<Layout> {
+        let structure = match msg.view() {
+            gst::MessageView::Element(element) => element.get_structure()?,
+            _ => return None,
+        };
+        if structure.get_name() != LAYOUT {
+            return None;
+        }
+        Some(Layout {
+            columns: structure.get_some("columns").ok()?,
+            rows: structure.get_some("rows").ok()?,
+            cell_width: structure.get_some("cell-width").ok()?,
+            cell_height: structure.get_some("cell-height").ok()?,
+            texture_width: structure.get_some("texture-width").ok()?,
+            texture_height: structure.get_some("texture-height").ok()?,
+            power_of_two: structure.get_some("power-of-two").ok()?,
+        })
+    }
+
+    pub(crate) fn to_structure(&self) -> gst::Structure {
+        gst::Structure::builder(LAYOUT)
+            .field("columns", &self.columns)
+            .field("rows", &self.rows)
+            .field("cell-width", &self.cell_width)
+            .field("cell-height", &self.cell_height)
+            .field("texture-width", &self.texture_width)
+            .field("texture-height", &self.texture_height)
+            .field("power-of-two", &self.power_of_two)
+            .build()
+    }
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}x{} frames of {}x{} in {}x{} textures",
+            self.columns,
+            self.rows,
+            self.cell_width,
+            self.cell_height,
+            self.texture_width,
+            self.texture_height
+        )
+    }
+}
+
+fn previous_power_of_two(n: u32) -> u32 {
+    1 << (31 - n.leading_zeros())
+}
@@ -0,0 +1,79 @@
+//! Pristine encodes videos into Minecraft Bedrock resource and behaviour packs
+//! that play them back as particles.
+//!
+//! ```no_run
+//! let report = pristine::Encoder::builder()
+//!     .source("video.mp4")
+//!     .name("my_video")
+//!     .fps(20)
+//!     .build()?
+//!     .run()?;
+//! println!("{} frames in {} atlases", report.frames, report.atlases);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! Several videos can share one pack with [`EncoderBuilder::playlist`], each clip getting
+//! its own particle namespace and scoreboard objective, and a `play_<clip>` function.
+//!
+//! The crate also builds as a GStreamer plugin providing the `pristineenc` element:
+//!
+//! ```text
+//! GST_PLUGIN_PATH=target/release gst-launch-1.0 filesrc location=video.mp4 ! decodebin \
+//!     ! videoconvert ! videorate ! video/x-raw,framerate=20/1 \
+//!     ! pristineenc pack-name=my_video height=20 ! fakesink
+//! ```
+//!
+//! and the `pristineatlas` element, which only tiles frames into PNG atlases for other
+//! exporters to consume:
+//!
+//! ```text
+//! gst-launch-1.0 filesrc location=video.mp4 ! decodebin ! videoconvert \
+//!     ! pristineatlas width=2 height=20 ! multifilesink location=atlas_%d.png
+//! ```
+//!
+//! Each atlas buffer spans the stream frames from its offset to its end offset, so
+//! [`Atlas::from_buffer`] can read it back with its caps and hand it to a [`PackWriter`].
+#[macro_use]
+extern crate glib;
+#[macro_use]
+extern crate gstreamer as gst;
+
+mod atlas;
+mod compositor;
+mod convert;
+mod encoder;
+mod error;
+mod layout;
+mod pack;
+mod process;
+mod source;
+mod subtitles;
+
+pub use atlas::{Atlas, Tiler};
+pub use encoder::{Encoder, EncoderBuilder, Progress, Report};
+pub use error::PristineError;
+pub use layout::Layout;
+pub use pack::{Anchor, AtlasComplete, PackConfig, PackWriter};
+pub use subtitles::Cue;
+
+/// Registers the `pristineenc` and `pristineatlas` elements with GStreamer for use in this
+/// process.
+pub fn register() -> Result<(), glib::BoolError> {
+    process::register(None)
+}
+
+fn plugin_init(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    process::register(Some(plugin))
+}
+
+gst_plugin_define!(
+    pristine,
+    env!("CARGO_PKG_DESCRIPTION"),
+    plugin_init,
+    env!("CARGO_PKG_VERSION"),
+    "MIT/X11",
+    env!("CARGO_PKG_NAME"),
+    env!("CARGO_PKG_NAME"),
+    env!("CARGO_PKG_REPOSITORY"),
+    "2020-09-01"
+);
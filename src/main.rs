@@ -1,14 +1,105 @@
-#[macro_use]
-extern crate glib;
 extern crate clap;
-extern crate serde_json;
 use clap::{App, Arg};
-use gst::prelude::*;
-use gstreamer as gst;
-mod process;
-use std::fs;
-use std::io::Write;
-use std::time::SystemTime;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::time::Duration;
+
+/// Parses a position given in seconds, `mm:ss` or `hh:mm:ss`, with optional fractions of
+/// a second.
+fn parse_time(value: &str) -> Option<Duration> {
+    let parts = value.split(':').collect::<Vec<_>>();
+    if parts.len() > 3 {
+        return None;
+    }
+    let mut seconds = 0.0;
+    for part in parts {
+        let part = part.trim().parse::<f64>().ok()?;
+        if part < 0.0 || !part.is_finite() {
+            return None;
+        }
+        seconds = seconds * 60.0 + part;
+    }
+    Some(Duration::from_secs_f64(seconds))
+}
+
+/// Parses a colour given as `#rrggbb`, `#rrggbbaa` or `transparent` into RGBA.
+fn parse_colour(value: &str) -> Option<[u8; 4]> {
+    if value.eq_ignore_ascii_case("transparent") {
+        return Some([0, 0, 0, 0]);
+    }
+    let hex = value.trim_start_matches('#');
+    let channel = |i: usize| u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok();
+    match hex.len() {
+        6 => Some([channel(0)?, channel(1)?, channel(2)?, 255]),
+        8 => Some([channel(0)?, channel(1)?, channel(2)?, channel(3)?]),
+        _ => None,
+    }
+}
+
+/// Parses a billboard size given as `<width>x<height>` in world units.
+fn parse_size(value: &str) -> Option<(f64, f64)> {
+    let mut dims = value.split('x').map(|d| d.trim().parse::<f64>());
+    match (dims.next(), dims.next(), dims.next()) {
+        (Some(Ok(width)), Some(Ok(height)), None) => Some((width, height)),
+        _ => None,
+    }
+}
+
+/// Reports an invalid value of `--arg` and exits with the code of configuration errors.
+fn invalid(arg: &str, value: &str) -> ! {
+    let e = pristine::PristineError::Config(format!("Invalid --{} {}", arg, value));
+    eprintln!("{}", e);
+    std::process::exit(e.exit_code())
+}
+
+/// Parses the value of `--arg`, if given, exiting when it is invalid.
+fn parse_arg<T>(
+    matches: &clap::ArgMatches,
+    arg: &str,
+    parse: impl Fn(&str) -> Option<T>,
+) -> Option<T> {
+    matches
+        .value_of(arg)
+        .map(|value| parse(value).unwrap_or_else(|| invalid(arg, value)))
+}
+
+/// Formats a duration as `m:ss`.
+fn clock(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// Redraws the progress bar, falling back to a spinner while the source duration is
+/// unknown.
+fn draw(bar: &ProgressBar, progress: &pristine::Progress) {
+    let mut message = format!(
+        "{} frames, {:.1} frames/s, {} atlases",
+        progress.frames,
+        progress.fps(),
+        progress.atlases
+    );
+    if progress.clips > 1 {
+        message = format!("clip {}/{}: {}", progress.clip + 1, progress.clips, message);
+    }
+    match progress.fraction() {
+        Some(fraction) => {
+            bar.set_style(
+                ProgressStyle::default_bar()
+                    .template("[{wide_bar}] {percent:>3}% {msg}")
+                    .progress_chars("=> "),
+            );
+            bar.set_length(1000);
+            bar.set_position((fraction * 1000.0) as u64);
+            if let Some(eta) = progress.eta() {
+                message.push_str(&format!(", ETA {}", clock(eta)));
+            }
+        }
+        None => {
+            bar.set_style(ProgressStyle::default_spinner().template("{spinner} {msg}"));
+            bar.tick();
+        }
+    }
+    bar.set_message(&message);
+}
 
 fn main() {
     let matches = App::new("Pristine")
@@ -18,8 +109,9 @@ fn main() {
         .arg(Arg::with_name("path")
             .short("p")
             .long("path")
-            .help("Sets the path of the video.")
-            .takes_value(true))
+            .help("Sets the path of the video, animated GIF or APNG, or a directory or glob of images. Several paths make a playlist.")
+            .takes_value(true)
+            .multiple(true))
         .arg(Arg::with_name("name")
             .short("n")
             .long("name")
@@ -47,99 +139,213 @@ fn main() {
             .short("s")
             .help("The particle scale")
             .takes_value(true))
+        .arg(Arg::with_name("size")
+            .long("size")
+            .help("Sets the particle size in world units as <width>x<height>, overriding the scale")
+            .takes_value(true))
         .arg(Arg::with_name("facing-camera-mode")
             .long("facing-camera-mode")
             .short("fcm")
             .help("Sets facing camera mode")
             .takes_value(true))
+        .arg(Arg::with_name("max-dimension")
+            .long("max-dimension")
+            .help("Sets the maximum width and height of an atlas texture")
+            .takes_value(true))
+        .arg(Arg::with_name("power-of-two")
+            .long("power-of-two")
+            .help("Pads atlas textures to power-of-two dimensions"))
+        .arg(Arg::with_name("width")
+            .long("width")
+            .help("Scales frames to this width, letterboxing them to keep their aspect ratio")
+            .requires("height")
+            .takes_value(true))
+        .arg(Arg::with_name("height")
+            .long("height")
+            .help("Scales frames to this height, letterboxing them to keep their aspect ratio")
+            .requires("width")
+            .takes_value(true))
+        .arg(Arg::with_name("max-frame-dimension")
+            .long("max-frame-dimension")
+            .help("Downscales frames keeping their aspect ratio until they fit this size")
+            .takes_value(true))
+        .arg(Arg::with_name("background")
+            .long("background")
+            .help("Sets the letterbox colour as #rrggbb, #rrggbbaa or transparent")
+            .takes_value(true))
+        .arg(Arg::with_name("no-audio")
+            .long("no-audio")
+            .help("Skips the audio track"))
+        .arg(Arg::with_name("audio-segment")
+            .long("audio-segment")
+            .help("Splits the audio into segments of this many seconds, re-synced at every boundary")
+            .takes_value(true))
+        .arg(Arg::with_name("start")
+            .long("start")
+            .help("Starts encoding at this position, in seconds or [hh:]mm:ss")
+            .takes_value(true))
+        .arg(Arg::with_name("end")
+            .long("end")
+            .help("Stops encoding at this position, in seconds or [hh:]mm:ss")
+            .takes_value(true))
+        .arg(Arg::with_name("duration")
+            .long("duration")
+            .help("Stops encoding after this long, in seconds or [hh:]mm:ss")
+            .conflicts_with("end")
+            .takes_value(true))
+        .arg(Arg::with_name("subtitles")
+            .long("subtitles")
+            .help("Shows the cues of an SRT or WebVTT file as in-game captions")
+            .takes_value(true))
+        .arg(Arg::with_name("embedded-subtitles")
+            .long("embedded-subtitles")
+            .help("Shows the subtitle track of the video as in-game captions")
+            .conflicts_with("subtitles"))
+        .arg(Arg::with_name("caption-mode")
+            .long("caption-mode")
+            .help("Shows captions on the actionbar or as a subtitle")
+            .possible_values(&["actionbar", "subtitle"])
+            .takes_value(true))
+        .arg(Arg::with_name("tick")
+            .long("tick")
+            .help("Runs the playback loop from tick.json instead of a repeating command block"))
+        .arg(Arg::with_name("anchor")
+            .long("anchor")
+            .help("Emits the video from entities with this tag, from a dummy entity with \"entity\", or at x,y,z")
+            .takes_value(true))
+        .arg(Arg::with_name("quiet")
+            .short("q")
+            .long("quiet")
+            .help("Hides the progress bar"))
         .get_matches();
 
-    let path = matches.value_of("path").expect("Missing flag <path>!");
+    let paths = matches
+        .values_of("path")
+        .expect("Missing flag <path>!")
+        .collect::<Vec<_>>();
     let name = matches.value_of("name").or(Some("pristine")).unwrap();
-    let fps = matches
-        .value_of("fps")
-        .unwrap_or("20")
-        .parse::<u32>()
-        .unwrap_or(20);
-    let spi = matches
-        .value_of("spi")
-        .unwrap_or("1")
-        .parse::<u32>()
-        .unwrap_or(1);
-    let scale = matches
-        .value_of("scale")
-        .unwrap_or("1")
-        .parse::<u32>()
-        .unwrap_or(1);
+    let count = |value: &str| value.parse::<u32>().ok();
+    let fps = parse_arg(&matches, "fps", count).unwrap_or(20);
+    let spi = parse_arg(&matches, "spi", count).unwrap_or(1);
+    let scale = parse_arg(&matches, "scale", |value| value.parse::<f64>().ok()).unwrap_or(1.0);
+    let size = parse_arg(&matches, "size", parse_size);
     let fcm = matches
         .value_of("facing-camera-mode")
         .unwrap_or("lookat_xyz");
+    let max_dimension = parse_arg(&matches, "max-dimension", count).unwrap_or(4096);
+    let power_of_two = matches.occurrences_of("power-of-two") > 0;
+    let pixels = |value: &str| value.parse::<u32>().ok().filter(|&pixels| pixels > 0);
+    let frame_width = parse_arg(&matches, "width", pixels);
+    let frame_height = parse_arg(&matches, "height", pixels);
+    let max_frame_dimension = parse_arg(&matches, "max-frame-dimension", pixels);
+    let background = parse_arg(&matches, "background", parse_colour).unwrap_or([0, 0, 0, 255]);
     let looping = matches.occurrences_of("loop") > 0;
+    let audio = matches.occurrences_of("no-audio") == 0;
+    let audio_segment = parse_arg(&matches, "audio-segment", count).unwrap_or(0);
+    let time = |arg: &str| parse_arg(&matches, arg, parse_time);
+    let start = time("start");
+    let end = match time("duration") {
+        Some(duration) => Some(start.unwrap_or_default() + duration),
+        None => time("end"),
+    };
+    let embedded_subtitles = matches.occurrences_of("embedded-subtitles") > 0;
+    let caption_mode = matches.value_of("caption-mode").unwrap_or("actionbar");
+    let tick = matches.occurrences_of("tick") > 0;
+    let anchor = matches
+        .value_of("anchor")
+        .map(|value| {
+            value.parse::<pristine::Anchor>().unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(e.exit_code())
+            })
+        })
+        .unwrap_or_default();
+    let quiet = matches.occurrences_of("quiet") > 0;
     let description = matches
         .value_of("description")
         .or(Some("Pristine Video Pack.Powered by CAIMEO. LICENSE MIT."))
         .unwrap();
 
-    let mut r = fs::File::create(".cache.json").unwrap();
-    if let Err(e) = r.write_all(
-        &serde_json::to_string(&serde_json::json!(
-            {
-                "name":name,
-                "description":description,
-                "fcm":fcm,
-            }
-        ))
-        .unwrap()
-        .as_ref(),
-    ) {
-        panic!("{}", e);
-    }
-
-    gst::init().expect("Unable to init gstreamer.");
-    process::register().expect("Unable to register plugin.");
-    let t1 = SystemTime::now();
-    let encode_pipeline: &str = &format!(
-        "filesrc location={} ! decodebin ! videoconvert ! Encoder width={} height={} scale={} loop={} ! filesink location=.cache",
-        path, spi, fps, scale, looping
-    );
+    let bar = if quiet {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new_spinner()
+    };
+    let atlas_bar = bar.clone();
+    let layout_bar = bar.clone();
+    let progress_bar = bar.clone();
 
-    println!("{}", encode_pipeline);
-    let pipeline = gst::parse_launch(encode_pipeline).unwrap();
-    // let pipeline = pipeline.dynamic_cast::<gst::Pipeline>().unwrap();
-    // let enc = pipeline.get_by_name("enc").unwrap();
-    //
-    // enc.set_property("height", &fps);
-    // enc.set_property("width", &spi);
-    // enc.set_property("name", &name);
-    // enc.set_property("description", &description);
-    // enc.set_property("loop", &looping);
-    pipeline
-        .set_state(gst::State::Playing)
-        .expect("Unable to set the pipeline to the `Playing` state");
-
-    let bus = pipeline.get_bus().unwrap();
-    for msg in bus.iter_timed(gst::CLOCK_TIME_NONE) {
-        use gst::MessageView;
-        match msg.view() {
-            MessageView::Error(err) => {
-                eprintln!(
-                    "Error received from element {:?}: {}",
-                    err.get_src().map(|s| s.get_path_string()),
-                    err.get_error()
-                );
-                eprintln!("Debugging information: {:?}", err.get_debug());
-                break;
+    let mut builder = pristine::Encoder::builder();
+    if let Some((width, height)) = size {
+        builder = builder.size(width, height);
+    }
+    if let (Some(width), Some(height)) = (frame_width, frame_height) {
+        builder = builder.frame_size(width, height);
+    }
+    if let Some(max_frame_dimension) = max_frame_dimension {
+        builder = builder.max_frame_dimension(max_frame_dimension);
+    }
+    if let Some(subtitles) = matches.value_of("subtitles") {
+        builder = builder.subtitles(subtitles);
+    }
+    if let Some(start) = start {
+        builder = builder.start(start);
+    }
+    if let Some(end) = end {
+        builder = builder.end(end);
+    }
+    let report = builder
+        .playlist(paths)
+        .name(name)
+        .description(description)
+        .fps(fps)
+        .spi(spi)
+        .scale(scale)
+        .looping(looping)
+        .facing_camera_mode(fcm)
+        .max_dimension(max_dimension)
+        .power_of_two(power_of_two)
+        .background(background)
+        .embedded_subtitles(embedded_subtitles)
+        .caption_mode(caption_mode)
+        .tick(tick)
+        .anchor(anchor)
+        .audio(audio)
+        .audio_segment(audio_segment)
+        .on_atlas(move |atlas| match &atlas.location {
+            Some(location) => atlas_bar.println(format!(
+                "Saved atlas {} (frames {}-{}) to {}",
+                atlas.index,
+                atlas.first_frame,
+                atlas.last_frame,
+                location.display()
+            )),
+            None => atlas_bar.println(format!(
+                "Completed atlas {} (frames {}-{})",
+                atlas.index, atlas.first_frame, atlas.last_frame
+            )),
+        })
+        .on_layout(move |layout| layout_bar.println(format!("Atlas layout: {}", layout)))
+        .on_progress(move |progress| draw(&progress_bar, progress))
+        .build()
+        .and_then(|encoder| encoder.run());
+    bar.finish_and_clear();
+    match report {
+        Ok(report) => {
+            println!(
+                "Encoded {} frames into {} atlases at {}",
+                report.frames,
+                report.atlases,
+                report.pack.display()
+            );
+            if !report.clips.is_empty() {
+                println!("Clips: {}", report.clips.join(", "));
             }
-            MessageView::Eos(..) => break,
-            _ => (),
+            println!("Time used: {}", report.elapsed.as_millis())
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(e.exit_code())
         }
     }
-
-    pipeline
-        .set_state(gst::State::Null)
-        .expect("Unable to set the pipeline to the `Null` state");
-    println!(
-        "Time used: {}",
-        SystemTime::now().duration_since(t1).unwrap().as_millis()
-    )
 }
@@ -0,0 +1,914 @@
+use std::fmt;
+use std::fs::create_dir_all;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::atlas::Atlas;
+use crate::error::PristineError;
+use crate::subtitles::Cue;
+
+/// The loop function runs once per game tick.
+const TICKS_PER_SECOND: u32 = 20;
+/// An actionbar message fades after about three seconds, so long cues are shown again.
+const ACTIONBAR_REFRESH_TICKS: u32 = 40;
+/// Interval in seconds between the `seek_<n>` functions of a video with a single audio track.
+const SEEK_STEP_SECONDS: u32 = 10;
+/// Billboard height at scale 1, which keeps 16:9 videos at the historical `[16, 9]` size.
+const BILLBOARD_HEIGHT: f64 = 9.0;
+/// Tag of the entities the video is emitted from by default.
+pub const DEFAULT_SCREEN_TAG: &str = "pristine_screen";
+
+/// How the pack is laid out and played back.
+#[derive(Debug, Clone)]
+pub struct PackConfig {
+    /// Name of the pack, also used as particle namespace and scoreboard objective unless
+    /// `namespace` is set.
+    pub name: String,
+    /// Description written to the pack manifests.
+    pub description: String,
+    /// Directory the pack is written into.
+    pub output: PathBuf,
+    /// Facing camera mode of the particle billboard.
+    pub fcm: String,
+    /// Frames per second of the atlases.
+    pub fps: u32,
+    /// Scale of the billboard sized after the video's aspect ratio.
+    pub scale: f64,
+    /// Billboard size in world units, overriding the scale.
+    pub size: Option<(f64, f64)>,
+    /// Looping animation.
+    pub looping: bool,
+    /// Length in seconds of each audio segment, 0 for a single track.
+    pub audio_segment: u32,
+    /// Save the atlas textures into the pack.
+    pub write_textures: bool,
+    /// Captions shown along the video, timed from its first frame.
+    pub subtitles: Vec<Cue>,
+    /// Where captions are shown: `actionbar` or `subtitle`.
+    pub caption: String,
+    /// Particle namespace and scoreboard objective of a clip sharing the pack with others,
+    /// `None` when the pack holds a single video named after it.
+    pub namespace: Option<String>,
+    /// Write the pack manifests, which only the first clip of a playlist does.
+    pub init: bool,
+    /// Run the loop from `tick.json` while the video plays instead of from a repeating
+    /// command block.
+    pub tick: bool,
+    /// What the particles are emitted from.
+    pub anchor: Anchor,
+}
+
+/// What the particles of a video are emitted from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Anchor {
+    /// Every entity wearing the tag. `setup` tags the armor stand nearest to where it runs.
+    Tag(String),
+    /// The `<pack>:screen` dummy entity defined by the behaviour pack, which `setup`
+    /// summons where it runs.
+    Entity,
+    /// Fixed absolute coordinates.
+    Position(f64, f64, f64),
+}
+
+impl Default for Anchor {
+    fn default() -> Self {
+        Anchor::Tag(DEFAULT_SCREEN_TAG.to_string())
+    }
+}
+
+impl Anchor {
+    /// Command emitting `particle` from the anchor of the pack called `name`.
+    fn emit(&self, name: &str, particle: &str) -> String {
+        match self {
+            Anchor::Tag(tag) => {
+                format!("execute @e[tag={}] ~ ~ ~ particle {} ~ ~ ~", tag, particle)
+            }
+            Anchor::Entity => format!(
+                "execute @e[type={}:screen] ~ ~ ~ particle {} ~ ~ ~",
+                name, particle
+            ),
+            Anchor::Position(x, y, z) => format!("particle {} {} {} {}", particle, x, y, z),
+        }
+    }
+
+    /// Commands of `setup` moving the anchor of the pack called `name` to where it runs.
+    fn setup(&self, name: &str) -> Vec<String> {
+        match self {
+            Anchor::Tag(tag) => vec![
+                format!("tag @e[tag={t}] remove {t}", t = tag),
+                format!("tag @e[type=armor_stand,c=1] add {}", tag),
+            ],
+            Anchor::Entity => vec![
+                format!("kill @e[type={}:screen]", name),
+                format!("summon {}:screen ~ ~ ~", name),
+            ],
+            Anchor::Position(..) => vec![],
+        }
+    }
+
+    /// Defines the dummy screen entity in the behaviour pack at `root`, if it is the anchor.
+    ///
+    /// The entity has no client definition, so it is never rendered.
+    fn write_entity(&self, root: &str, name: &str) -> Result<(), PristineError> {
+        if *self != Anchor::Entity {
+            return Ok(());
+        }
+        let dir = format!("{}/behavior_pack/entities", root);
+        create_dir_all(&dir)?;
+        let json = serde_json::to_string_pretty(&serde_json::json!({
+            "format_version": "1.16.0",
+            "minecraft:entity": {
+                "description": {
+                    "identifier": format!("{}:screen", name),
+                    "is_spawnable": false,
+                    "is_summonable": true,
+                    "is_experimental": false
+                },
+                "components": {
+                    "minecraft:physics": { "has_gravity": false, "has_collision": false },
+                    "minecraft:collision_box": { "width": 0.1, "height": 0.1 },
+                    "minecraft:damage_sensor": {
+                        "triggers": { "cause": "all", "deals_damage": false }
+                    },
+                    "minecraft:pushable": { "is_pushable": false, "is_pushable_by_piston": false },
+                    "minecraft:persistent": {}
+                }
+            }
+        }))
+        .map_err(|e| PristineError::Pack(e.to_string()))?;
+        std::fs::write(format!("{}/screen.json", dir), json)?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Anchor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Anchor::Tag(tag) => f.write_str(tag),
+            Anchor::Entity => f.write_str("entity"),
+            Anchor::Position(x, y, z) => write!(f, "{},{},{}", x, y, z),
+        }
+    }
+}
+
+impl FromStr for Anchor {
+    type Err = PristineError;
+
+    /// Parses `entity`, coordinates given as `x,y,z` or `x y z`, or an entity tag.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || PristineError::Config(format!("Invalid anchor {}", value));
+        if value == "entity" {
+            return Ok(Anchor::Entity);
+        }
+        let parts = value
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>();
+        if parts.len() == 3 {
+            let coordinates = parts
+                .iter()
+                .map(|part| part.parse::<f64>().ok().filter(|c| c.is_finite()))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(invalid)?;
+            return Ok(Anchor::Position(coordinates[0], coordinates[1], coordinates[2]));
+        }
+        let tag = value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.');
+        if value.is_empty() || !tag {
+            return Err(invalid());
+        }
+        Ok(Anchor::Tag(value.to_string()))
+    }
+}
+
+/// Name of the element message posted for every atlas added to the pack.
+const ATLAS_COMPLETE: &str = "pristine-atlas-complete";
+
+/// An atlas that was added to the pack, as announced on the bus by `pristineenc` in a
+/// `pristine-atlas-complete` element message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AtlasComplete {
+    /// Position of the atlas in the stream, starting at 0.
+    pub index: u32,
+    /// Path of the texture, `None` when textures are not written to disk.
+    pub location: Option<PathBuf>,
+    /// Stream frame number of the first frame in the atlas.
+    pub first_frame: u32,
+    /// Stream frame number of the last frame in the atlas.
+    pub last_frame: u32,
+    pub texture_width: u32,
+    pub texture_height: u32,
+    /// Identifiers of the particles playing the atlas, one per column.
+    pub particles: Vec<String>,
+}
+
+impl AtlasComplete {
+    /// Reads the atlas back from a `pristine-atlas-complete` message, `None` for any other
+    /// message.
+    pub fn from_message(msg: &gst::Message) -> Option<AtlasComplete> {
+        let structure = match msg.view() {
+            gst::MessageView::Element(element) => element.get_structure()?,
+            _ => return None,
+        };
+        if structure.get_name() != ATLAS_COMPLETE {
+            return None;
+        }
+        let particles = structure
+            .get_some::<gst::Array>("particles")
+            .ok()?
+            .as_slice()
+            .iter()
+            .filter_map(|particle| particle.get::<String>().ok().flatten())
+            .collect();
+        Some(AtlasComplete {
+            index: structure.get_some("index").ok()?,
+            location: structure
+                .get::<String>("location")
+                .ok()
+                .flatten()
+                .map(PathBuf::from),
+            first_frame: structure.get_some("first-frame").ok()?,
+            last_frame: structure.get_some("last-frame").ok()?,
+            texture_width: structure.get_some("texture-width").ok()?,
+            texture_height: structure.get_some("texture-height").ok()?,
+            particles,
+        })
+    }
+
+    pub(crate) fn to_structure(&self) -> gst::Structure {
+        let particles = self
+            .particles
+            .iter()
+            .map(|particle| particle as &dyn glib::ToSendValue)
+            .collect::<Vec<_>>();
+        let mut structure = gst::Structure::builder(ATLAS_COMPLETE)
+            .field("index", &self.index)
+            .field("first-frame", &self.first_frame)
+            .field("last-frame", &self.last_frame)
+            .field("texture-width", &self.texture_width)
+            .field("texture-height", &self.texture_height)
+            .field("particles", &gst::Array::new(&particles))
+            .build();
+        if let Some(location) = &self.location {
+            structure.set("location", &location.to_string_lossy().into_owned());
+        }
+        structure
+    }
+}
+
+/// Sound event of the audio track, or of one of its segments.
+fn sound_id(name: &str, segment: Option<u32>) -> String {
+    match segment {
+        Some(i) => format!("{}.audio_{}", name, i),
+        None => format!("{}.audio", name),
+    }
+}
+
+fn texture_path(dir: &str, name: &str, index: u32) -> String {
+    format!("{}/{}_{}.png", dir, name, index)
+}
+
+/// Makes the game run `function` every tick, through the `tick.json` of the behaviour pack.
+fn write_tick_json(root: &str, function: &str) -> Result<(), PristineError> {
+    let dir = format!("{}/behavior_pack/functions", root);
+    create_dir_all(&dir)?;
+    let json = serde_json::to_string_pretty(&serde_json::json!({ "values": [function] }))
+        .map_err(|e| PristineError::Pack(e.to_string()))?;
+    std::fs::write(format!("{}/tick.json", dir), json)?;
+    Ok(())
+}
+
+/// Score tick at which the given position of the video shows up in game.
+fn seconds_tick(time: std::time::Duration) -> u32 {
+    (time.as_secs_f64() * TICKS_PER_SECOND as f64).round() as u32
+}
+
+/// Resolves `path` against the working directory, so that the pack ends up where it was
+/// requested whichever thread writes it.
+pub(crate) fn absolute(path: &Path) -> Result<PathBuf, PristineError> {
+    if path.is_absolute() {
+        return Ok(path.to_path_buf());
+    }
+    Ok(std::env::current_dir()?.join(path))
+}
+
+/// Writes `value` as pretty-printed JSON.
+fn write_json(path: &str, value: &serde_json::Value) -> Result<(), PristineError> {
+    let json =
+        serde_json::to_string_pretty(value).map_err(|e| PristineError::Pack(e.to_string()))?;
+    std::fs::write(path, json)?;
+    Ok(())
+}
+
+/// A UUID for the `part` of the pack called `name`, formatted as a random (version 4) one.
+///
+/// It only depends on its arguments, so that writing the pack again keeps its identity and
+/// the game replaces the previous version instead of listing both.
+fn uuid(name: &str, part: &str) -> String {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let half = |seed: u8| {
+        let mut hasher = DefaultHasher::new();
+        (name, part, seed).hash(&mut hasher);
+        hasher.finish()
+    };
+    let high = (half(0) & !0xf000) | 0x4000;
+    let low = (half(1) & !0xc000_0000_0000_0000) | 0x8000_0000_0000_0000;
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        high >> 32,
+        (high >> 16) & 0xffff,
+        high & 0xffff,
+        low >> 48,
+        low & 0xffff_ffff_ffff
+    )
+}
+
+/// The resource and behaviour packs under `root`.
+struct Pack {
+    root: String,
+    name: String,
+    description: String,
+}
+
+impl Pack {
+    fn new(root: &str, name: &str, description: &str) -> Pack {
+        Pack {
+            root: root.to_string(),
+            name: name.to_string(),
+            description: description.to_string(),
+        }
+    }
+
+    /// Writes the manifests of both packs, the behaviour pack depending on the resource
+    /// pack.
+    fn init_all(&self) -> Result<(), PristineError> {
+        let manifest = |kind: &str, module: &str, dependencies: serde_json::Value| {
+            serde_json::json!({
+                "format_version": 2,
+                "header": {
+                    "name": self.name,
+                    "description": self.description,
+                    "uuid": uuid(&self.name, kind),
+                    "version": [1, 0, 0],
+                    "min_engine_version": [1, 16, 0]
+                },
+                "modules": [{
+                    "type": module,
+                    "uuid": uuid(&self.name, &format!("{}_{}", kind, module)),
+                    "version": [1, 0, 0]
+                }],
+                "dependencies": dependencies
+            })
+        };
+        for dir in &["resource_pack", "behavior_pack"] {
+            create_dir_all(format!("{}/{}", self.root, dir))?;
+        }
+        write_json(
+            &format!("{}/resource_pack/manifest.json", self.root),
+            &manifest("resource_pack", "resources", serde_json::json!([])),
+        )?;
+        write_json(
+            &format!("{}/behavior_pack/manifest.json", self.root),
+            &manifest(
+                "behavior_pack",
+                "data",
+                serde_json::json!([{
+                    "uuid": uuid(&self.name, "resource_pack"),
+                    "version": [1, 0, 0]
+                }]),
+            ),
+        )
+    }
+
+    /// Writes a function of the behaviour pack.
+    fn add_fn(&self, name: &str, commands: Vec<String>) -> Result<(), PristineError> {
+        let dir = format!("{}/behavior_pack/functions", self.root);
+        create_dir_all(&dir)?;
+        let mut function = commands.join("\n");
+        function.push('\n');
+        std::fs::write(format!("{}/{}.mcfunction", dir, name), function)?;
+        Ok(())
+    }
+
+    /// Writes a particle of the resource pack, in a file named after its identifier.
+    fn add_particle(
+        &self,
+        id: &str,
+        material: &str,
+        texture: &str,
+        components: serde_json::Value,
+    ) -> Result<(), PristineError> {
+        let dir = format!("{}/resource_pack/particles", self.root);
+        create_dir_all(&dir)?;
+        write_json(
+            &format!("{}/{}.json", dir, id.replace(':', "_")),
+            &serde_json::json!({
+                "format_version": "1.10.0",
+                "particle_effect": {
+                    "description": {
+                        "identifier": id,
+                        "basic_render_parameters": {
+                            "material": material,
+                            "texture": texture
+                        }
+                    },
+                    "components": components
+                }
+            }),
+        )
+    }
+}
+
+/// A column of an atlas, played by a single particle.
+struct Strip {
+    start: u32,
+    frames: u32,
+}
+
+/// Builds the resource and behaviour packs playing back a stream of atlases.
+pub struct PackWriter {
+    config: PackConfig,
+    aspect: f64,
+    pack: Pack,
+    root: String,
+    path: String,
+    strips: Vec<Strip>,
+    frames: u32,
+    atlases: u32,
+    textures: Vec<String>,
+}
+
+impl PackWriter {
+    /// Lays out an empty pack for a video of the given display aspect ratio.
+    pub fn new(config: PackConfig, aspect: f64) -> Result<PackWriter, PristineError> {
+        let output = absolute(&config.output)?;
+        let root = output.join(&config.name).to_string_lossy().into_owned();
+        let path = format!("{}/resource_pack/textures/frames", root);
+        create_dir_all(&path)?;
+        let pack = Pack::new(&root, &config.name, &config.description);
+        if config.init {
+            pack.init_all()?;
+        }
+        Ok(PackWriter {
+            config,
+            aspect,
+            pack,
+            root,
+            path,
+            strips: vec![],
+            frames: 0,
+            atlases: 0,
+            textures: vec![],
+        })
+    }
+
+    /// Root directory of the pack.
+    pub fn root(&self) -> &str {
+        &self.root
+    }
+
+    /// Particle namespace and scoreboard objective of the video.
+    pub fn namespace(&self) -> &str {
+        self.config.namespace.as_ref().unwrap_or(&self.config.name)
+    }
+
+    /// Name of one of the video's functions, prefixed with the namespace of a playlist clip.
+    fn function(&self, function: &str) -> String {
+        match &self.config.namespace {
+            Some(namespace) => format!("{}_{}", namespace, function),
+            None => function.to_string(),
+        }
+    }
+
+    /// Forgets the atlases added so far, for a stream that starts over. The pack itself is
+    /// kept, and textures written again under the same names.
+    pub fn reset(&mut self) {
+        self.strips.clear();
+        self.frames = 0;
+        self.atlases = 0;
+        self.textures.clear();
+    }
+
+    /// Number of frames added so far.
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /// Number of atlases added so far.
+    pub fn atlases(&self) -> u32 {
+        self.atlases
+    }
+
+    /// Paths of the atlas textures written so far.
+    pub fn textures(&self) -> &[String] {
+        &self.textures
+    }
+
+    /// Saves the atlas if textures are written to disk and registers its particles.
+    pub fn add_atlas(&mut self, atlas: &Atlas) -> Result<AtlasComplete, PristineError> {
+        let mut location = None;
+        if self.config.write_textures {
+            let texture = texture_path(&self.path, self.namespace(), atlas.index);
+            std::fs::write(&texture, &atlas.png)?;
+            location = Some(PathBuf::from(&texture));
+            self.textures.push(texture);
+        }
+        let particles = self.add_particles(atlas)?;
+        self.atlases += 1;
+        self.frames += atlas.frames;
+        Ok(AtlasComplete {
+            index: atlas.index,
+            location,
+            first_frame: atlas.first_frame,
+            last_frame: atlas.first_frame + atlas.frames - 1,
+            texture_width: atlas.layout.texture_width,
+            texture_height: atlas.layout.texture_height,
+            particles,
+        })
+    }
+
+    /// Writes the functions playing the video back, along with the audio track when the
+    /// stream had one.
+    ///
+    /// A single video starts playing for the nearest player as soon as `setup` runs, which
+    /// also moves the anchor to where it runs, the clips of a playlist wait for their
+    /// `play_` function instead.
+    ///
+    /// `play`, `pause`, `resume`, `stop` and `seek_<n>`, which jumps `n` seconds in, control
+    /// playback for the player running them. A paused player keeps their score and wears
+    /// the `<name>_paused` tag, which every command of the loop skips. Particles already
+    /// shown and the audio track cannot be paused, so the video picks up again at its next
+    /// column and the audio at its next segment.
+    pub fn finish(&self, audio: bool) -> Result<(), PristineError> {
+        let name = self.namespace();
+        let mut setup = vec![
+            format!("scoreboard objectives remove {}", name),
+            format!("scoreboard objectives add {n} dummy {n}", n = name),
+        ];
+        if self.config.namespace.is_none() {
+            setup.extend(self.config.anchor.setup(&self.config.name));
+            self.config.anchor.write_entity(&self.root, &self.config.name)?;
+            setup.push(format!("scoreboard players add @p {} 0", name));
+        }
+        self.pack.add_fn(&self.function("setup"), setup)?;
+        let mut timeline: Vec<String> = vec![];
+        if audio {
+            self.write_sound_definitions()?;
+            timeline.extend(self.sound_commands());
+        }
+        timeline.extend(self.caption_commands());
+        for (i, strip) in self.strips.iter().enumerate() {
+            let particle = format!("{}:i_{}", name, i);
+            timeline.push(self.at(self.tick(strip.start).to_string(), &self.config.anchor.emit(&self.config.name, &particle)))
+        }
+        let end = self.tick(self.frames);
+        timeline.push(self.at(format!("..{}", end), &format!("scoreboard players add @s {} 1", name)));
+        self.pack.add_fn(&self.function("loop"), timeline)?;
+        self.write_controls(audio, end)?;
+        if self.config.tick {
+            self.write_tick(end)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the `tick` function, which runs the loop while a player's score is within the
+    /// video, and registers it in `tick.json` unless the video is a clip of a playlist.
+    ///
+    /// The objective is created on the fly, so the pack plays without running `setup`
+    /// first.
+    fn write_tick(&self, end: u32) -> Result<(), PristineError> {
+        let name = self.namespace();
+        self.pack.add_fn(
+            &self.function("tick"),
+            vec![
+                format!("scoreboard objectives add {n} dummy {n}", n = name),
+                format!(
+                    "execute @a[scores={{{n}=0..{t}}},c=1] ~ ~ ~ function {f}",
+                    n = name,
+                    t = end,
+                    f = self.function("loop")
+                ),
+            ],
+        )?;
+        if self.config.namespace.is_none() {
+            write_tick_json(&self.root, "tick")?;
+        }
+        Ok(())
+    }
+
+    /// Runs `command` as every player whose score matches `ticks` and who is not paused.
+    fn at(&self, ticks: String, command: &str) -> String {
+        format!(
+            "execute @a[scores={{{n}={t}}},tag=!{n}_paused] ~ ~ ~ {c}",
+            n = self.namespace(),
+            t = ticks,
+            c = command
+        )
+    }
+
+    /// Writes the `play`, `pause`, `resume`, `stop` and `seek_<n>` functions of a video
+    /// lasting `end` ticks.
+    fn write_controls(&self, audio: bool, end: u32) -> Result<(), PristineError> {
+        let name = self.namespace();
+        let paused = format!("{}_paused", name);
+        let stop_sounds = if audio { self.stop_sound_commands() } else { vec![] };
+        let seek = |tick: u32| {
+            let mut commands = stop_sounds.clone();
+            commands.push(format!("scoreboard players set @s {} {}", name, tick));
+            commands
+        };
+
+        let mut play = seek(0);
+        play.push(format!("tag @s remove {}", paused));
+        self.pack.add_fn(&self.function("play"), play)?;
+        let mut pause = stop_sounds.clone();
+        pause.push(format!("tag @s add {}", paused));
+        self.pack.add_fn(&self.function("pause"), pause)?;
+        self.pack.add_fn(
+            &self.function("resume"),
+            vec![format!("tag @s remove {}", paused)],
+        )?;
+        let mut stop = stop_sounds.clone();
+        stop.push(format!("scoreboard players reset @s {}", name));
+        stop.push(format!("tag @s remove {}", paused));
+        self.pack.add_fn(&self.function("stop"), stop)?;
+
+        // Seeking to the start of an audio segment lets the loop play it right away.
+        let step = match self.config.audio_segment {
+            0 => SEEK_STEP_SECONDS,
+            segment => segment,
+        };
+        for seconds in (0..).step_by(step as usize) {
+            let tick = seconds * TICKS_PER_SECOND;
+            if seconds > 0 && tick >= end {
+                break;
+            }
+            self.pack
+                .add_fn(&self.function(&format!("seek_{}", seconds)), seek(tick))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the functions of a playlist whose clips were added to the pack under the given
+    /// namespaces.
+    ///
+    /// `setup`, `loop`, `pause`, `resume` and `stop` run those of every clip, `play_<clip>`
+    /// starts a clip for the players running it and stops the others, and `select` plays
+    /// the clip whose position in the playlist is the player's score in the objective named
+    /// after the pack. With `tick` the `tick` functions of the clips are run every tick.
+    /// The clips share the anchor, which the playlist's `setup` moves.
+    pub fn write_playlist(
+        output: &Path,
+        name: &str,
+        description: &str,
+        clips: &[String],
+        tick: bool,
+        anchor: &Anchor,
+    ) -> Result<(), PristineError> {
+        let output = absolute(output)?;
+        let root = output.join(name).to_string_lossy().into_owned();
+        let pack = Pack::new(&root, name, description);
+        let mut setup = vec![
+            format!("scoreboard objectives remove {}", name),
+            format!("scoreboard objectives add {n} dummy {n}", n = name),
+        ];
+        setup.extend(anchor.setup(name));
+        anchor.write_entity(&root, name)?;
+        setup.extend(clips.iter().map(|clip| format!("function {}_setup", clip)));
+        pack.add_fn("setup", setup)?;
+        pack.add_fn(
+            "loop",
+            clips
+                .iter()
+                .map(|clip| format!("function {}_loop", clip))
+                .collect(),
+        )?;
+
+        // Every clip has its own controls, those of the playlist apply to all of them.
+        let all = |control: &str| {
+            clips
+                .iter()
+                .map(|clip| format!("function {}_{}", clip, control))
+                .collect::<Vec<_>>()
+        };
+        pack.add_fn("stop", all("stop"))?;
+        pack.add_fn("pause", all("pause"))?;
+        pack.add_fn("resume", all("resume"))?;
+        if tick {
+            pack.add_fn("tick", all("tick"))?;
+            write_tick_json(&root, "tick")?;
+        }
+        for clip in clips {
+            pack.add_fn(
+                &format!("play_{}", clip),
+                vec!["function stop".to_string(), format!("function {}_play", clip)],
+            )?;
+        }
+        pack.add_fn(
+            "select",
+            clips
+                .iter()
+                .enumerate()
+                .map(|(i, clip)| {
+                    format!(
+                        "execute @a[scores={{{n}={i}}}] ~ ~ ~ function play_{c}",
+                        n = name,
+                        i = i,
+                        c = clip
+                    )
+                })
+                .collect(),
+        )?;
+        Ok(())
+    }
+
+    /// Size of the particle billboard: the explicit dimensions when set, otherwise the
+    /// display aspect ratio of the video multiplied by the scale.
+    fn billboard_size(&self) -> [f64; 2] {
+        match self.config.size {
+            Some((width, height)) => [width, height],
+            None => [
+                BILLBOARD_HEIGHT * self.aspect * self.config.scale,
+                BILLBOARD_HEIGHT * self.config.scale,
+            ],
+        }
+    }
+
+    /// Score tick at which the given frame shows up in game.
+    fn tick(&self, frame: u32) -> u32 {
+        (frame as f64 * TICKS_PER_SECOND as f64 / self.config.fps as f64).round() as u32
+    }
+
+    /// Registers the particles playing the atlas, returning their identifiers.
+    ///
+    /// Frames fill the atlas column by column, and a flipbook can only step by a constant
+    /// `step_UV`, so every column gets a particle of its own that is scheduled after the
+    /// previous one.
+    fn add_particles(&mut self, atlas: &Atlas) -> Result<Vec<String>, PristineError> {
+        let layout = &atlas.layout;
+        let (cell_width, cell_height) = (layout.cell_width, layout.cell_height);
+        let fps = self.config.fps;
+        let size = self.billboard_size();
+        let mut start = self.strips.last().map_or(0, |s| s.start + s.frames);
+        let mut particles = vec![];
+        for column in 0..((atlas.frames + layout.rows - 1) / layout.rows) {
+            let count = (atlas.frames - column * layout.rows).min(layout.rows);
+            let lifetime = count as f64 / fps as f64;
+            let particle = format!("{}:i_{}", self.namespace(), self.strips.len());
+            self.pack.add_particle(&particle, "particles_alpha",&format!("textures/frames/{}_{}.png", self.namespace(), atlas.index),serde_json::json!({
+                "minecraft:emitter_lifetime_once": {
+                    "active_time": lifetime
+                },
+                "minecraft:emitter_rate_instant":{
+                    "num_particles":1
+                },
+                "minecraft:particle_lifetime_expression":{
+                    "max_lifetime": lifetime,
+                },
+                "minecraft:emitter_shape_point": {
+                    "offset":[0,0,0],
+                    "direction":[1,0,0]
+                },
+                "minecraft:particle_appearance_billboard":{
+                    "facing_camera_mode": self.config.fcm,
+                    "size": size,
+                    "uv": {
+                        "texture_width": layout.texture_width,
+                        "texture_height": layout.texture_height,
+                        "flipbook": {
+                            "base_UV": [column * cell_width, 0],
+                            "frames_per_second": fps,
+                            "loop": self.config.looping,
+                            "max_frame": count,
+                            "size_UV": [cell_width, cell_height],
+                            "step_UV": [0, cell_height],
+                            "stretch_to_lifetime":false
+                        }
+                    },
+
+                }
+            }))?;
+            self.strips.push(Strip { start, frames: count });
+            particles.push(particle);
+            start += count;
+        }
+        Ok(particles)
+    }
+
+    /// `titleraw` commands showing every cue at the tick it starts, either on the actionbar
+    /// or as a subtitle under an empty title.
+    fn caption_commands(&self) -> Vec<String> {
+        let at = |tick: u32, command: String| self.at(tick.to_string(), &command);
+        let mut commands = vec![];
+        for cue in &self.config.subtitles {
+            let text = serde_json::json!({ "rawtext": [{ "text": cue.text }] });
+            let start = seconds_tick(cue.start);
+            let end = seconds_tick(cue.end).max(start + 1);
+            if self.config.caption == "subtitle" {
+                commands.push(at(start, format!("titleraw @s times 0 {} 0", end - start)));
+                commands.push(at(start, format!("titleraw @s subtitle {}", text)));
+                commands.push(at(start, r#"titleraw @s title {"rawtext":[{"text":""}]}"#.to_string()));
+            } else {
+                for tick in (start..end).step_by(ACTIONBAR_REFRESH_TICKS as usize) {
+                    commands.push(at(tick, format!("titleraw @s actionbar {}", text)));
+                }
+            }
+        }
+        commands
+    }
+
+    /// Segments the audio track is split into, `None` when it is a single file.
+    fn audio_segments(&self) -> Option<u32> {
+        let segment = self.config.audio_segment;
+        if segment == 0 {
+            return None;
+        }
+        let seconds = (self.frames as f64 / self.config.fps as f64).ceil() as u32;
+        Some(((seconds + segment - 1) / segment).max(1))
+    }
+
+    /// Registers the audio track in `sounds/sound_definitions.json`.
+    fn write_sound_definitions(&self) -> Result<(), PristineError> {
+        let name = self.namespace();
+        let sound = |id: String, file: String| {
+            (
+                id,
+                serde_json::json!({
+                    "category": "record",
+                    "sounds": [{ "name": format!("sounds/{}", file), "stream": true }]
+                }),
+            )
+        };
+        let mut definitions: serde_json::Map<String, serde_json::Value> =
+            match self.audio_segments() {
+                Some(segments) => (0..segments)
+                    .map(|i| sound(sound_id(name, Some(i)), format!("{}_{}", name, i)))
+                    .collect(),
+                None => vec![sound(sound_id(name, None), name.to_string())]
+                    .into_iter()
+                    .collect(),
+            };
+        let dir = format!("{}/resource_pack/sounds", self.root);
+        create_dir_all(&dir)?;
+        let file = format!("{}/sound_definitions.json", dir);
+        // Clips of a playlist share the file, so the sounds of earlier ones are kept.
+        if let Ok(existing) = std::fs::read_to_string(&file) {
+            let existing: serde_json::Value = serde_json::from_str(&existing)
+                .map_err(|e| PristineError::Pack(e.to_string()))?;
+            if let Some(existing) = existing["sound_definitions"].as_object() {
+                for (id, sound) in existing {
+                    definitions.entry(id.clone()).or_insert_with(|| sound.clone());
+                }
+            }
+        }
+        let json = serde_json::to_string_pretty(&serde_json::json!({
+            "format_version": "1.14.0",
+            "sound_definitions": definitions
+        }))
+        .map_err(|e| PristineError::Pack(e.to_string()))?;
+        std::fs::write(file, json)?;
+        Ok(())
+    }
+
+    /// `playsound` commands starting the track at tick 0 and re-syncing it at the start of
+    /// every segment.
+    fn sound_commands(&self) -> Vec<String> {
+        let name = self.namespace();
+        let play = |tick: u32, sound: String| {
+            self.at(tick.to_string(), &format!("playsound {} @s", sound))
+        };
+        match self.audio_segments() {
+            Some(segments) => {
+                let mut commands = vec![];
+                for i in 0..segments {
+                    let tick = i * self.config.audio_segment * TICKS_PER_SECOND;
+                    if i > 0 {
+                        commands.push(self.at(
+                            tick.to_string(),
+                            &format!("stopsound @s {}", sound_id(name, Some(i - 1))),
+                        ));
+                    }
+                    commands.push(play(tick, sound_id(name, Some(i))));
+                }
+                commands
+            }
+            None => vec![play(0, sound_id(name, None))],
+        }
+    }
+
+    /// `stopsound` commands silencing every sound of the video for the running player.
+    fn stop_sound_commands(&self) -> Vec<String> {
+        let name = self.namespace();
+        let sounds = match self.audio_segments() {
+            Some(segments) => (0..segments).map(|i| sound_id(name, Some(i))).collect(),
+            None => vec![sound_id(name, None)],
+        };
+        sounds
+            .into_iter()
+            .map(|sound| format!("stopsound @s {}", sound))
+            .collect()
+    }
+}
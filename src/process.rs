@@ -1,71 +1,130 @@
-extern crate gstreamer as gst;
-extern crate image;
-
 use glib::subclass;
 use glib::subclass::prelude::*;
 use gst::subclass::prelude::*;
 use gst_video::prelude::*;
 use gst_video::subclass::prelude::*;
 use gstreamer_video as gst_video;
+use once_cell::sync::Lazy;
 use parking_lot::Mutex;
 
-use self::image::GenericImage;
-use image::{DynamicImage, ImageBuffer, Rgba, RgbaImage};
-use mc_rs::pack::McPack;
-use std::fs::create_dir_all;
-use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::atlas::{self, Atlas, Tiler, TilerSettings, TilingState};
+use crate::compositor;
+use crate::error::PristineError;
+use crate::pack::{Anchor, PackConfig, PackWriter, DEFAULT_SCREEN_TAG};
+use crate::subtitles::{self, Cue};
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "pristine",
+        gst::DebugColorFlags::empty(),
+        Some("Pristine encoder"),
+    )
+});
 
 #[derive(Debug, Clone)]
 struct Settings {
-    width: u32,
-    height: u32,
-    scale: u32,
+    tiler: TilerSettings,
+    scale: f64,
+    size: (f64, f64),
     r#loop: bool,
+    name: String,
+    description: String,
+    fcm: String,
+    output: String,
+    audio: bool,
+    audio_segment: u32,
+    write_textures: bool,
+    subtitles: Option<String>,
+    subtitle_offset: u64,
+    caption: String,
+    namespace: Option<String>,
+    init_pack: bool,
+    tick: bool,
+    anchor: String,
 }
 
 impl Default for Settings {
     fn default() -> Self {
         Settings {
-            width: 2,
-            height: 20,
-            scale: 1,
+            tiler: TilerSettings::default(),
+            scale: 1.0,
+            size: (0.0, 0.0),
             r#loop: false,
+            name: "pristine".to_string(),
+            description: "Pristine Video Pack.Powered by CAIMEO. LICENSE MIT.".to_string(),
+            fcm: "lookat_xyz".to_string(),
+            output: ".".to_string(),
+            audio: false,
+            audio_segment: 0,
+            write_textures: true,
+            subtitles: None,
+            subtitle_offset: 0,
+            caption: "actionbar".to_string(),
+            namespace: None,
+            init_pack: true,
+            tick: false,
+            anchor: DEFAULT_SCREEN_TAG.to_string(),
         }
     }
 }
 
-static PROPERTIES: [subclass::Property; 4] = [
-    subclass::Property("width", |name| {
-        glib::ParamSpec::uint(
+#[derive(Debug, Clone, Default)]
+struct Stats {
+    frames: u32,
+    atlases: u32,
+    textures: Vec<String>,
+}
+
+static PROPERTIES: [subclass::Property; 28] = [
+    subclass::Property("width", atlas::param_spec),
+    subclass::Property("height", atlas::param_spec),
+    subclass::Property("max-dimension", atlas::param_spec),
+    subclass::Property("power-of-two", atlas::param_spec),
+    subclass::Property("frame-width", atlas::param_spec),
+    subclass::Property("frame-height", atlas::param_spec),
+    subclass::Property("background", atlas::param_spec),
+    subclass::Property("write-textures", |name| {
+        glib::ParamSpec::boolean(
             name,
-            "Width",
-            "The width of the single image",
-            1,
-            std::u32::MAX,
-            1,
+            "Write Textures",
+            "Save the atlases into the pack besides pushing them downstream",
+            true,
             glib::ParamFlags::READWRITE,
         )
     }),
-    subclass::Property("height", |name| {
-        glib::ParamSpec::uint(
+    subclass::Property("scale", |name| {
+        glib::ParamSpec::double(
             name,
-            "Height",
-            "The height of the single image",
-            1,
-            std::u32::MAX,
-            1,
+            "Scale",
+            "The scale of the particle",
+            0.0,
+            std::f64::MAX,
+            1.0,
             glib::ParamFlags::READWRITE,
         )
     }),
-    subclass::Property("scale", |name| {
-        glib::ParamSpec::uint(
+    subclass::Property("billboard-width", |name| {
+        glib::ParamSpec::double(
             name,
-            "Scale",
-            "The scale of the particle",
-            1,
-            std::u32::MAX,
-            1,
+            "Billboard Width",
+            "The width of the particle in world units, 0 to derive it from the video",
+            0.0,
+            std::f64::MAX,
+            0.0,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("billboard-height", |name| {
+        glib::ParamSpec::double(
+            name,
+            "Billboard Height",
+            "The height of the particle in world units, 0 to derive it from the video",
+            0.0,
+            std::f64::MAX,
+            0.0,
             glib::ParamFlags::READWRITE,
         )
     }),
@@ -78,195 +137,268 @@ static PROPERTIES: [subclass::Property; 4] = [
             glib::ParamFlags::READWRITE,
         )
     }),
+    // `name` already belongs to GstObject, so the pack name gets its own property.
+    subclass::Property("pack-name", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Pack Name",
+            "The name of the pack, also used as particle namespace and scoreboard objective",
+            Some("pristine"),
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("description", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Description",
+            "The description written to the pack manifests",
+            Some("Pristine Video Pack.Powered by CAIMEO. LICENSE MIT."),
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("facing-camera-mode", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Facing Camera Mode",
+            "The facing camera mode of the particle billboard",
+            Some("lookat_xyz"),
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("output", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Output",
+            "The directory the pack is written into",
+            Some("."),
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("audio", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Audio",
+            "Register the audio track written next to the textures and play it along",
+            false,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("audio-segment", |name| {
+        glib::ParamSpec::uint(
+            name,
+            "Audio Segment",
+            "Length in seconds of each audio segment, 0 for a single track",
+            0,
+            std::u32::MAX,
+            0,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("subtitles", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Subtitles",
+            "An SRT or WebVTT file whose cues are shown along the video",
+            None,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("subtitle-offset", |name| {
+        glib::ParamSpec::uint64(
+            name,
+            "Subtitle Offset",
+            "Position of the first frame in the subtitles, in milliseconds",
+            0,
+            std::u64::MAX,
+            0,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("caption-mode", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Caption Mode",
+            "Where captions are shown: actionbar or subtitle",
+            Some("actionbar"),
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("namespace", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Namespace",
+            "Particle namespace and scoreboard objective of a playlist clip, unset for the pack name",
+            None,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("init-pack", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Init Pack",
+            "Write the pack manifests, unset when adding a clip to an existing pack",
+            true,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("tick", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Tick",
+            "Run the loop from tick.json while the video plays",
+            false,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("anchor", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Anchor",
+            "What the video is emitted from: an entity tag, entity for a dummy entity, or x,y,z",
+            Some(DEFAULT_SCREEN_TAG),
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("frames", |name| {
+        glib::ParamSpec::uint(
+            name,
+            "Frames",
+            "The number of frames encoded so far",
+            0,
+            std::u32::MAX,
+            0,
+            glib::ParamFlags::READABLE,
+        )
+    }),
+    subclass::Property("atlases", |name| {
+        glib::ParamSpec::uint(
+            name,
+            "Atlases",
+            "The number of atlases completed so far",
+            0,
+            std::u32::MAX,
+            0,
+            glib::ParamFlags::READABLE,
+        )
+    }),
+    subclass::Property("textures", |name| {
+        glib::ParamSpec::boxed(
+            name,
+            "Textures",
+            "The paths of the atlas textures written to disk so far",
+            Vec::<String>::static_type(),
+            glib::ParamFlags::READABLE,
+        )
+    }),
 ];
 
+impl Settings {
+    /// How the pack writer lays out and plays back the pack.
+    fn pack_config(&self) -> PackConfig {
+        PackConfig {
+            name: self.name.clone(),
+            description: self.description.clone(),
+            output: PathBuf::from(&self.output),
+            fcm: self.fcm.clone(),
+            fps: self.tiler.height,
+            scale: self.scale,
+            size: if self.size.0 > 0.0 && self.size.1 > 0.0 {
+                Some(self.size)
+            } else {
+                None
+            },
+            looping: self.r#loop,
+            audio_segment: self.audio_segment,
+            write_textures: self.write_textures,
+            subtitles: vec![],
+            caption: self.caption.clone(),
+            namespace: self.namespace.clone(),
+            init: self.init_pack,
+            tick: self.tick,
+            anchor: Anchor::default(),
+        }
+    }
+}
+
+/// Display aspect ratio of the video.
+fn aspect(info: &gst_video::VideoInfo) -> f64 {
+    let par = info.par();
+    (info.width() as f64 * *par.numer() as f64) / (info.height() as f64 * *par.denom() as f64)
+}
+
+/// Tiles frames like `pristineatlas` and hands every atlas over to the pack writer.
 struct State {
-    video_info: gst_video::VideoInfo,
-    context: Option<DynamicImage>,
-    name: String,
-    pack: Option<McPack>,
-    path: String,
-    width: u32,
-    scale: u32,
-    height: u32,
-    ptr: (u32, u32),
-    index: u32,
-    sub_index: u32,
-    fcm: String,
-    r#loop: bool,
+    tiler: Tiler,
+    writer: PackWriter,
 }
+
 impl State {
-    pub fn new(video_info: gst_video::VideoInfo) -> Self {
-        Self {
-            video_info,
-            context: None,
-            name: "pristine".to_string(),
-            pack: None,
-            path: String::from("tmp"),
-            ptr: (0, 0),
-            index: 0,
-            sub_index: 0,
-            scale: 1,
-            fcm: "direction_z".to_string(),
-            width: 0,
-            height: 0,
-            r#loop: false,
+    fn new(video_info: gst_video::VideoInfo, settings: &Settings) -> Result<Self, PristineError> {
+        // Letterboxed frames are played back at the aspect ratio of their cell.
+        let ratio = if settings.tiler.letterboxed() {
+            settings.tiler.frame_width as f64 / settings.tiler.frame_height as f64
+        } else {
+            aspect(&video_info)
+        };
+        let mut config = settings.pack_config();
+        config.anchor = settings.anchor.parse()?;
+        if let Some(path) = &settings.subtitles {
+            // Cues are timed from the start of the source, the pack from its first frame.
+            let offset = Duration::from_millis(settings.subtitle_offset);
+            config.subtitles = subtitles::read(Path::new(path))?
+                .into_iter()
+                .filter(|cue| cue.end > offset)
+                .map(|cue| Cue {
+                    start: cue.start.checked_sub(offset).unwrap_or_default(),
+                    end: cue.end - offset,
+                    text: cue.text,
+                })
+                .collect();
         }
+        let writer = PackWriter::new(config, ratio)?;
+        let tiler = settings.tiler.tiler(video_info);
+        Ok(State { tiler, writer })
     }
-    pub fn reset(&mut self, settings: Settings) {
-        let width = self.video_info.width();
-        let height = self.video_info.height();
-        self.width = settings.width;
-        self.height = settings.height;
-        self.r#loop = settings.r#loop;
-        self.scale = settings.scale;
-        let mut cache = std::fs::File::open(".cache.json").unwrap();
-        let mut contents = String::new();
-        cache
-            .read_to_string(&mut contents)
-            .expect("Cant read cache to string");
-        let v: Cache = serde_json::from_str(&contents).unwrap();
-        #[derive(serde::Serialize, serde::Deserialize)]
-        struct Cache<'a> {
-            name: &'a str,
-            fcm: &'a str,
-            description: &'a str,
-        }
-        self.name = v.name.to_string();
-        self.fcm = v.fcm.to_string();
-        self.path = format!("{}/resource_pack/textures/frames", v.name);
-        create_dir_all(&self.path);
-        self.new_pack(v.name, v.description);
-        if let Some(pack) = &self.pack {
-            pack.init_all()
+
+    fn stats(&self) -> Stats {
+        Stats {
+            frames: self.tiler.frames(),
+            atlases: self.writer.atlases(),
+            textures: self.writer.textures().to_vec(),
         }
-        let img = ImageBuffer::new(width * settings.width, height * settings.height);
-        self.context = Some(DynamicImage::ImageRgba8(img));
     }
+}
 
-    fn new_pack(&mut self, name: &str, description: &str) {
-        self.pack = Some(McPack::new(name, description));
+impl TilingState for State {
+    fn tiler(&mut self) -> &mut Tiler {
+        &mut self.tiler
     }
 
-    fn write_data(&mut self, data: &[u8], rate: u32) {
-        if let Some(writer) = self.context.as_mut() {
-            let img: RgbaImage = ImageBuffer::from_raw(
-                self.video_info.width(),
-                self.video_info.height(),
-                Vec::from(&data[..]),
-            )
-            .unwrap();
-            image::imageops::replace(
-                writer,
-                &img,
-                self.ptr.0 * self.video_info.width(),
-                self.ptr.1 * self.video_info.height(),
-            );
-            // self.sub_index += 1;
-            // if let Some(pack) = &self.pack {
-            //     pack.resource.add_particle(&format!("{}:i_{}", self.name, self.sub_index), "particles_alpha",&format!("textures/frames/{}_{}.png", self.name, self.index),serde_json::json!({
-            //                 "minecraft:emitter_lifetime_once": {
-            //                     "active_time": 0.5
-            //                 },
-            //                 "minecraft:emitter_rate_instant":{
-            //                     "num_particles":1
-            //                 },
-            //                 "minecraft:particle_lifetime_expression":{
-            //                     "max_lifetime": 0.5,
-            //                 },
-            //                 "minecraft:emitter_shape_point": {
-            //                     "offset":[0,0,0],
-            //                     "direction":[1,0,0]
-            //                 },
-            //                 "minecraft:particle_appearance_billboard":{
-            //                     "facing_camera_mode": self.fcm,
-            //                     "size": [
-            //                         16, 9
-            //                     ],
-            //
-            //                     "uv": {
-            //                         "texture_width": self.width * self.video_info.width(),
-            //                         "texture_height": self.height * self.video_info.height(),
-            //                         "uv":[self.ptr.0 * self.video_info.width(), self.ptr.1 * self.video_info.height()],
-            //                         "uv_size":[self.video_info.width(), self.video_info.height()]
-            //                     },
-            //
-            //                 }
-            //             }));
-            // }
-
-            if self.ptr.1 == self.height - 1 {
-                if self.ptr.0 == self.width - 1 {
-                    if let Err(e) = writer.save(&Path::new(&format!(
-                        "{}/{}_{}.png",
-                        self.path.clone(),
-                        self.name,
-                        self.index
-                    ))) {
-                        println!("{}: {}", e, self.path.clone());
-                    }
-                    println!("File Saved on PNG::{}", self.index);
-                    if let Some(pack) = &self.pack {
-                        pack.resource.add_particle(&format!("{}:i_{}", self.name, self.index), "particles_alpha",&format!("textures/frames/{}_{}.png", self.name, self.index),serde_json::json!({
-                            "minecraft:emitter_lifetime_once": {
-                                "active_time": self.width
-                            },
-                            "minecraft:emitter_rate_instant":{
-                                "num_particles":1
-                            },
-                            "minecraft:particle_lifetime_expression":{
-                                "max_lifetime": self.width,
-                            },
-                            "minecraft:emitter_shape_point": {
-                                "offset":[0,0,0],
-                                "direction":[1,0,0]
-                            },
-                            "minecraft:particle_appearance_billboard":{
-                                "facing_camera_mode": self.fcm,
-                                "size": [
-                                    16, 9
-                                ],
-                                "uv": {
-                                    "texture_width": self.width * self.video_info.width(),
-                                    "texture_height": self.height * self.video_info.height(),
-                                    "flipbook": {
-                                        "base_UV": [/*"math.floor(variable.particle_age)*".to_string() + &self.video_info.width().to_string()*/0, 0],
-                                        "frames_per_second": self.height,
-                                        "loop": self.r#loop,
-                                        "max_frame": self.height * self.width,
-                                        "size_UV": [self.video_info.width(), self.video_info.height()],
-                                        "step_UV": [0, /*"(math.floor(variable.particle_age)%2?1;-1)*".to_string() + &self.video_info.height().to_string()*/270],
-                                        "stretch_to_lifetime":false
-                                    }
-                                },
-
-                            }
-                        }));
-                    }
-
-                    self.index += 1;
-                    self.ptr.0 = 0;
-                } else {
-                    self.ptr.0 += 1;
-                }
-
-                self.ptr.1 = 0;
-            } else {
-                self.ptr.1 += 1;
-            }
-        } else {
-            unreachable!()
-        }
+    /// Adds a completed atlas to the pack and announces it with a `pristine-atlas-complete`
+    /// element message.
+    fn add_atlas(
+        &mut self,
+        element: &gst_video::VideoEncoder,
+        atlas: &Atlas,
+    ) -> Result<(), PristineError> {
+        let completed = self.writer.add_atlas(atlas)?;
+        gst_debug!(CAT, obj: element, "Completed atlas {:?}", completed);
+        let msg = gst::Message::new_element(completed.to_structure())
+            .src(Some(element))
+            .build();
+        element.post_message(&msg)?;
+        Ok(())
     }
 }
 
 pub struct Encoder {
     state: Mutex<Option<State>>,
     settings: Mutex<Settings>,
+    stats: Mutex<Stats>,
 }
 
 impl ObjectSubclass for Encoder {
-    const NAME: &'static str = "Encoder";
+    const NAME: &'static str = "PristineEnc";
     type ParentType = gst_video::VideoEncoder;
     type Instance = gst::subclass::ElementInstanceStruct<Self>;
     type Class = subclass::simple::ClassStruct<Self>;
@@ -274,49 +406,16 @@ impl ObjectSubclass for Encoder {
     glib_object_subclass!();
 
     fn class_init(klass: &mut subclass::simple::ClassStruct<Self>) {
-        klass.set_metadata("Pristine", "Encoder/Video", "Video to frames", "CAIMEO");
-
-        let sink_caps = gst::Caps::new_simple(
-            "video/x-raw",
-            &[
-                (
-                    "format",
-                    &gst::List::new(&[
-                        // &gst_video::VideoFormat::Gray8.to_str(),
-                        // &gst_video::VideoFormat::Gray16Be.to_str(),
-                        // &gst_video::VideoFormat::Rgb.to_str(),
-                        &gst_video::VideoFormat::Rgba.to_str(),
-                    ]),
-                ),
-                ("width", &gst::IntRange::<i32>::new(1, std::i32::MAX)),
-                ("height", &gst::IntRange::<i32>::new(1, std::i32::MAX)),
-                (
-                    "framerate",
-                    &gst::FractionRange::new(
-                        gst::Fraction::new(1, 1),
-                        // frame-delay timing in gif is a multiple of 10ms -> max 100fps
-                        gst::Fraction::new(std::i32::MAX, 1),
-                    ),
-                ),
-            ],
+        klass.set_metadata(
+            "Pristine Encoder",
+            "Encoder/Video",
+            "Tiles video frames into atlases and writes a Minecraft Bedrock particle pack",
+            "CAIMEO",
         );
-        let sink_pad_template = gst::PadTemplate::new(
-            "sink",
-            gst::PadDirection::Sink,
-            gst::PadPresence::Always,
-            &sink_caps,
-        )
-        .unwrap();
-        klass.add_pad_template(sink_pad_template);
-        let src_caps = gst::Caps::new_simple("image/png", &[]);
-        let src_pad_template = gst::PadTemplate::new(
-            "src",
-            gst::PadDirection::Src,
-            gst::PadPresence::Always,
-            &src_caps,
-        )
-        .unwrap();
-        klass.add_pad_template(src_pad_template);
+
+        for template in atlas::pad_templates() {
+            klass.add_pad_template(template);
+        }
 
         klass.install_properties(&PROPERTIES);
     }
@@ -325,6 +424,7 @@ impl ObjectSubclass for Encoder {
         Self {
             state: Mutex::new(None),
             settings: Mutex::new(Default::default()),
+            stats: Mutex::new(Default::default()),
         }
     }
 }
@@ -333,23 +433,111 @@ impl ObjectImpl for Encoder {
     fn set_property(&self, _obj: &glib::Object, id: usize, value: &glib::Value) {
         let prop = &PROPERTIES[id];
 
+        if self.settings.lock().tiler.set_property(prop.0, value) {
+            return;
+        }
+
         match *prop {
-            subclass::Property("width", ..) => {
+            subclass::Property("write-textures", ..) => {
                 let mut settings = self.settings.lock();
-                settings.width = value.get_some::<u32>().expect("width must be u32")
+                settings.write_textures = value
+                    .get_some::<bool>()
+                    .expect("write-textures must be bool")
             }
-            subclass::Property("height", ..) => {
+            subclass::Property("scale", ..) => {
                 let mut settings = self.settings.lock();
-                settings.height = value.get_some::<u32>().expect("height must be u32")
+                settings.scale = value.get_some::<f64>().expect("scale must be f64")
             }
-            subclass::Property("scale", ..) => {
+            subclass::Property("billboard-width", ..) => {
+                let mut settings = self.settings.lock();
+                settings.size.0 = value
+                    .get_some::<f64>()
+                    .expect("billboard-width must be f64")
+            }
+            subclass::Property("billboard-height", ..) => {
                 let mut settings = self.settings.lock();
-                settings.scale = value.get_some::<u32>().expect("height must be u32")
+                settings.size.1 = value
+                    .get_some::<f64>()
+                    .expect("billboard-height must be f64")
             }
             subclass::Property("loop", ..) => {
                 let mut settings = self.settings.lock();
                 settings.r#loop = value.get_some::<bool>().expect("height must be u32")
             }
+            subclass::Property("pack-name", ..) => {
+                let mut settings = self.settings.lock();
+                settings.name = value
+                    .get::<String>()
+                    .expect("pack-name must be string")
+                    .unwrap_or_else(|| "pristine".to_string())
+            }
+            subclass::Property("description", ..) => {
+                let mut settings = self.settings.lock();
+                settings.description = value
+                    .get::<String>()
+                    .expect("description must be string")
+                    .unwrap_or_default()
+            }
+            subclass::Property("facing-camera-mode", ..) => {
+                let mut settings = self.settings.lock();
+                settings.fcm = value
+                    .get::<String>()
+                    .expect("facing-camera-mode must be string")
+                    .unwrap_or_else(|| "lookat_xyz".to_string())
+            }
+            subclass::Property("output", ..) => {
+                let mut settings = self.settings.lock();
+                settings.output = value
+                    .get::<String>()
+                    .expect("output must be string")
+                    .unwrap_or_else(|| ".".to_string())
+            }
+            subclass::Property("audio", ..) => {
+                let mut settings = self.settings.lock();
+                settings.audio = value.get_some::<bool>().expect("audio must be bool")
+            }
+            subclass::Property("audio-segment", ..) => {
+                let mut settings = self.settings.lock();
+                settings.audio_segment = value
+                    .get_some::<u32>()
+                    .expect("audio-segment must be u32")
+            }
+            subclass::Property("subtitles", ..) => {
+                let mut settings = self.settings.lock();
+                settings.subtitles = value.get::<String>().expect("subtitles must be string")
+            }
+            subclass::Property("subtitle-offset", ..) => {
+                let mut settings = self.settings.lock();
+                settings.subtitle_offset = value
+                    .get_some::<u64>()
+                    .expect("subtitle-offset must be u64")
+            }
+            subclass::Property("caption-mode", ..) => {
+                let mut settings = self.settings.lock();
+                settings.caption = value
+                    .get::<String>()
+                    .expect("caption-mode must be string")
+                    .unwrap_or_else(|| "actionbar".to_string())
+            }
+            subclass::Property("namespace", ..) => {
+                let mut settings = self.settings.lock();
+                settings.namespace = value.get::<String>().expect("namespace must be string")
+            }
+            subclass::Property("init-pack", ..) => {
+                let mut settings = self.settings.lock();
+                settings.init_pack = value.get_some::<bool>().expect("init-pack must be bool")
+            }
+            subclass::Property("tick", ..) => {
+                let mut settings = self.settings.lock();
+                settings.tick = value.get_some::<bool>().expect("tick must be bool")
+            }
+            subclass::Property("anchor", ..) => {
+                let mut settings = self.settings.lock();
+                settings.anchor = value
+                    .get::<String>()
+                    .expect("anchor must be string")
+                    .unwrap_or_else(|| DEFAULT_SCREEN_TAG.to_string())
+            }
             _ => unreachable!(),
         }
     }
@@ -357,107 +545,129 @@ impl ObjectImpl for Encoder {
     fn get_property(&self, _obj: &glib::Object, id: usize) -> Result<glib::Value, ()> {
         let prop = &PROPERTIES[id];
 
+        if let Some(value) = self.settings.lock().tiler.get_property(prop.0) {
+            return Ok(value);
+        }
+
         match *prop {
-            subclass::Property("width", ..) => {
-                let settings = self.settings.lock();
-                Ok(settings.width.to_value())
-            }
-            subclass::Property("height", ..) => {
+            subclass::Property("write-textures", ..) => {
                 let settings = self.settings.lock();
-                Ok(settings.height.to_value())
+                Ok(settings.write_textures.to_value())
             }
             subclass::Property("scale", ..) => {
                 let settings = self.settings.lock();
                 Ok(settings.scale.to_value())
             }
+            subclass::Property("billboard-width", ..) => {
+                let settings = self.settings.lock();
+                Ok(settings.size.0.to_value())
+            }
+            subclass::Property("billboard-height", ..) => {
+                let settings = self.settings.lock();
+                Ok(settings.size.1.to_value())
+            }
             subclass::Property("loop", ..) => {
                 let settings = self.settings.lock();
                 Ok(settings.r#loop.to_value())
             }
+            subclass::Property("pack-name", ..) => {
+                let settings = self.settings.lock();
+                Ok(settings.name.to_value())
+            }
+            subclass::Property("description", ..) => {
+                let settings = self.settings.lock();
+                Ok(settings.description.to_value())
+            }
+            subclass::Property("facing-camera-mode", ..) => {
+                let settings = self.settings.lock();
+                Ok(settings.fcm.to_value())
+            }
+            subclass::Property("output", ..) => {
+                let settings = self.settings.lock();
+                Ok(settings.output.to_value())
+            }
+            subclass::Property("audio", ..) => {
+                let settings = self.settings.lock();
+                Ok(settings.audio.to_value())
+            }
+            subclass::Property("audio-segment", ..) => {
+                let settings = self.settings.lock();
+                Ok(settings.audio_segment.to_value())
+            }
+            subclass::Property("subtitles", ..) => {
+                let settings = self.settings.lock();
+                Ok(settings.subtitles.to_value())
+            }
+            subclass::Property("subtitle-offset", ..) => {
+                let settings = self.settings.lock();
+                Ok(settings.subtitle_offset.to_value())
+            }
+            subclass::Property("caption-mode", ..) => {
+                let settings = self.settings.lock();
+                Ok(settings.caption.to_value())
+            }
+            subclass::Property("namespace", ..) => {
+                let settings = self.settings.lock();
+                Ok(settings.namespace.to_value())
+            }
+            subclass::Property("init-pack", ..) => {
+                let settings = self.settings.lock();
+                Ok(settings.init_pack.to_value())
+            }
+            subclass::Property("tick", ..) => {
+                let settings = self.settings.lock();
+                Ok(settings.tick.to_value())
+            }
+            subclass::Property("anchor", ..) => {
+                let settings = self.settings.lock();
+                Ok(settings.anchor.to_value())
+            }
+            subclass::Property("frames", ..) => Ok(self.stats().frames.to_value()),
+            subclass::Property("atlases", ..) => {
+                Ok(self.stats().atlases.to_value())
+            }
+            subclass::Property("textures", ..) => Ok(self.stats().textures.to_value()),
             _ => unimplemented!(),
         }
     }
 }
 
+impl Encoder {
+    /// Statistics of the running stream, or of the last one once the element has stopped.
+    fn stats(&self) -> Stats {
+        match &*self.state.lock() {
+            Some(state) => state.stats(),
+            None => self.stats.lock().clone(),
+        }
+    }
+}
+
 impl ElementImpl for Encoder {}
 
 impl VideoEncoderImpl for Encoder {
     fn stop(&self, _element: &gst_video::VideoEncoder) -> Result<(), gst::ErrorMessage> {
-        *self.state.lock() = None;
+        if let Some(state) = self.state.lock().take() {
+            *self.stats.lock() = state.stats();
+        }
         Ok(())
     }
 
+    fn flush(&self, _element: &gst_video::VideoEncoder) -> bool {
+        // A flushing seek restarts the stream, so the pack timeline starts over at zero.
+        if let Some(state) = self.state.lock().as_mut() {
+            state.tiler.reset();
+            state.writer.reset();
+        }
+        true
+    }
+
     fn finish(
         &self,
-        _element: &gst_video::VideoEncoder,
+        element: &gst_video::VideoEncoder,
     ) -> Result<gst::FlowSuccess, gst::FlowError> {
-        let mut state_guard = self.state.lock();
-        let state = state_guard.as_mut().ok_or(gst::FlowError::NotNegotiated)?;
-
-        // let mut pack = McPack::new(&state.name, "");
-        // pack.behavior.add_entity("minecraft:armor_stand", true, true, false);
-        // pack.behavior.add_animation("animation.video", {
-        //             let mut timeline:Vec<String> = vec![];
-        //             for i in 0..(state.index + 1) {
-        //                 timeline.push(format!("particle {}:i_{} ~ ~ ~", state.name, i))
-        //             }
-        //             timeline
-        //         }, state.r#loop, state.index + 1);
-        // pack.behavior.add_script("minecraft:armor_stand", "video","animation.video");
-        // pack.behavior.exit();
-        // if let Some(mut pack) = &state.pack {
-        //     pack.behavior.add_entity("armor_stand", true, true, false);
-        //     pack.behavior.add_animation("animation.video", {
-        //         let timeline:Vec<String> = vec![];
-        //         for i in 0..state.index + 1 {
-        //
-        //         }
-        //         timeline
-        //     }, state.r#loop, state.index + 1);
-        //     pack.behavior.add_script("armor_stand", "video", "")
-        // }
-        if let Some(writer) = state.context.as_mut() {
-            // let settings = self.settings.lock();
-            // let ptr = state.ptr;
-            // let bound = (state.width, state.height);
-            // let top: RgbaImage =
-            //     image::ImageBuffer::from_fn(settings.width, settings.height, |_, _| {
-            //         Rgba([225, 225, 225, 225])
-            //     });
-            // for x in ptr.0..bound.0 {
-            //     for y in ptr.1..bound.1 {
-            //         println!("{}, {}", x, y);
-            //
-            //         image::imageops::replace(writer, &top, x * settings.width, y * settings.height)
-            //     }
-            // }
-
-            writer
-                .save(&Path::new(&format!(
-                    "{}/{}_{}.png",
-                    state.path.clone(),
-                    state.name,
-                    state.index + 1
-                )))
-                .expect("Unable to save image");
-        }
-        if let Some(pack) = &state.pack {
-            pack.behavior.add_fn(
-                "setup",
-                vec![
-                    format!("scoreboard objectives remove {}", state.name),
-                    format!("scoreboard objectives add {n} dummy {n}", n = state.name),
-                    format!("scoreboard players add @p {} 0", state.name),
-                ],
-            );
-            let mut timeline: Vec<String> = vec![];
-            for i in 0..(state.index + 1) {
-                timeline.push(format!("execute @a[scores={{{s}={t}}}] ~ ~ ~ execute @e[type=armor_stand] ~ ~ ~ particle {s}:i_{i} ~ ~ ~", s = state.name,i = i, t = i * 60))
-            }
-            timeline.push(format!("execute @a[scores={{{n}=..{t}}}] ~ ~ ~ scoreboard players add @s {n} 1", n = state.name, t = state.index + 1));
-            pack.behavior.add_fn("loop", timeline);
-        }
-        Ok(gst::FlowSuccess::Ok)
+        // The audio branch may only be linked once the video is already flowing.
+        let audio = self.settings.lock().audio;
+        atlas::finish(element, &self.state, |state| state.writer.finish(audio))
     }
 
     fn set_format(
@@ -465,22 +675,19 @@ impl VideoEncoderImpl for Encoder {
         element: &gst_video::VideoEncoder,
         state: &gst_video::VideoCodecState<'static, gst_video::video_codec_state::Readable>,
     ) -> Result<(), gst::LoggableError> {
-        let video_info = state.get_info();
-        println!("Setting format {:?}", video_info);
-        {
-            let mut state = State::new(video_info);
-            let settings = self.settings.lock();
-            state.reset((*settings).clone());
-            *self.state.lock() = Some(state);
-        }
+        let settings = self.settings.lock().clone();
+        atlas::set_format(element, *CAT, &self.state, state, |video_info| {
+            State::new(video_info, &settings)
+        })
+    }
 
-        let output_state = element
-            .set_output_state(gst::Caps::new_simple("image/png", &[]), Some(state))
-            .expect("Failed to set output state");
-        element
-            .negotiate(output_state)
-            .expect("Failed to negotiate!");
-        Ok(())
+    fn propose_allocation(
+        &self,
+        element: &gst_video::VideoEncoder,
+        query: &mut gst::QueryRef,
+    ) -> Result<(), gst::ErrorMessage> {
+        atlas::propose_allocation(query);
+        self.parent_propose_allocation(element, query)
     }
 
     fn handle_frame(
@@ -488,27 +695,12 @@ impl VideoEncoderImpl for Encoder {
         element: &gst_video::VideoEncoder,
         frame: gst_video::VideoCodecFrame,
     ) -> Result<gst::FlowSuccess, gst::FlowError> {
-        let mut state_guard = self.state.lock();
-        let state = state_guard.as_mut().ok_or(gst::FlowError::NotNegotiated)?;
-        if frame.get_system_frame_number() % 40 == 0 {
-            println!("Sending frame {}", frame.get_system_frame_number());
-        }
-
-        {
-            let input_buffer = frame
-                .get_input_buffer()
-                .expect("frame without input buffer");
-
-            let input_map = input_buffer.map_readable().unwrap();
-            let data = input_map.as_slice();
-            state.write_data(data, frame.get_system_frame_number());
-        }
-
-        drop(state_guard);
-        element.finish_frame(Some(frame))
+        atlas::handle_frame(element, &self.state, frame)
     }
 }
 
-pub fn register() -> Result<(), glib::BoolError> {
-    gst::Element::register(None, "Encoder", gst::Rank::Primary, Encoder::get_type())
+// Rank::None keeps decodebin and encodebin from auto-plugging the encoder.
+pub fn register(plugin: Option<&gst::Plugin>) -> Result<(), glib::BoolError> {
+    gst::Element::register(plugin, "pristineenc", gst::Rank::None, Encoder::get_type())?;
+    compositor::register(plugin)
 }
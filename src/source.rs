@@ -0,0 +1,131 @@
+use image::{AnimationDecoder, RgbaImage};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::error::PristineError;
+
+/// Extensions of the still images an image sequence is made of.
+const IMAGE_EXTENSIONS: [&str; 8] = ["png", "jpg", "jpeg", "bmp", "tga", "tif", "tiff", "webp"];
+
+/// Where the frames of a pack come from.
+#[derive(Debug, Clone)]
+pub(crate) enum Source {
+    /// A video container, decoded by `decodebin`.
+    Media(PathBuf),
+    /// Still images shown one per frame, in file name order.
+    Images(Vec<PathBuf>),
+    /// An animated GIF or APNG, played with its own frame delays.
+    Animation(PathBuf),
+}
+
+impl Source {
+    /// Tells apart a directory or glob of images, an animated GIF or APNG and a video file.
+    pub(crate) fn detect(path: &Path) -> Result<Source, PristineError> {
+        if path.is_dir() {
+            let mut images = std::fs::read_dir(path)?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| is_image(path))
+                .collect::<Vec<_>>();
+            images.sort();
+            return sequence(images, path);
+        }
+
+        let pattern = path.to_string_lossy();
+        if pattern.contains(|c| c == '*' || c == '?' || c == '[') {
+            let images = glob::glob(&pattern)
+                .map_err(|e| PristineError::Config(format!("Invalid pattern {}: {}", pattern, e)))?
+                .filter_map(Result::ok)
+                .filter(|path| is_image(path))
+                .collect::<Vec<_>>();
+            return sequence(images, path);
+        }
+
+        match extension(path).as_deref() {
+            Some("gif") => Ok(Source::Animation(path.to_path_buf())),
+            Some("png") if is_apng(path)? => Ok(Source::Animation(path.to_path_buf())),
+            Some(_) if is_image(path) => Ok(Source::Images(vec![path.to_path_buf()])),
+            _ => Ok(Source::Media(path.to_path_buf())),
+        }
+    }
+}
+
+/// A decoded frame of an image source.
+pub(crate) struct Frame {
+    pub image: RgbaImage,
+    pub duration: Duration,
+}
+
+/// Decodes the frames of an image source, still images being shown for `1 / fps` seconds.
+///
+/// The decoders are not `Send`, so this has to run on the thread consuming the frames.
+pub(crate) fn frames(
+    source: &Source,
+    fps: u32,
+) -> Result<Box<dyn Iterator<Item = Result<Frame, PristineError>>>, PristineError> {
+    let still = Duration::from_secs_f64(1.0 / fps as f64);
+    match source {
+        Source::Images(paths) => {
+            let paths = paths.clone();
+            Ok(Box::new(paths.into_iter().map(move |path| {
+                Ok(Frame {
+                    image: image::open(&path)?.to_rgba8(),
+                    duration: still,
+                })
+            })))
+        }
+        Source::Animation(path) => {
+            let reader = BufReader::new(File::open(path)?);
+            let frames = if extension(path).as_deref() == Some("gif") {
+                image::codecs::gif::GifDecoder::new(reader)?.into_frames()
+            } else {
+                image::codecs::png::PngDecoder::new(reader)?.apng().into_frames()
+            };
+            Ok(Box::new(frames.map(|frame| {
+                let frame = frame?;
+                let (numer, denom) = frame.delay().numer_denom_ms();
+                let delay = numer as f64 / denom.max(1) as f64;
+                Ok(Frame {
+                    // Browsers show frames without a delay for 100ms, and so do we.
+                    duration: if delay > 0.0 {
+                        Duration::from_secs_f64(delay / 1000.0)
+                    } else {
+                        Duration::from_millis(100)
+                    },
+                    image: frame.into_buffer(),
+                })
+            })))
+        }
+        Source::Media(path) => Err(PristineError::Config(format!(
+            "{} is not an image source",
+            path.display()
+        ))),
+    }
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+}
+
+fn is_image(path: &Path) -> bool {
+    extension(path).map_or(false, |extension| {
+        IMAGE_EXTENSIONS.contains(&extension.as_str())
+    })
+}
+
+fn is_apng(path: &Path) -> Result<bool, PristineError> {
+    let decoder = image::codecs::png::PngDecoder::new(BufReader::new(File::open(path)?))?;
+    Ok(decoder.is_apng())
+}
+
+fn sequence(images: Vec<PathBuf>, path: &Path) -> Result<Source, PristineError> {
+    if images.is_empty() {
+        return Err(PristineError::Config(format!(
+            "No images found at {}",
+            path.display()
+        )));
+    }
+    Ok(Source::Images(images))
+}
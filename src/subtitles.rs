@@ -0,0 +1,158 @@
+use std::path::Path;
+use std::time::Duration;
+
+use crate::error::PristineError;
+
+/// A caption shown between two positions of the video.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cue {
+    pub start: Duration,
+    pub end: Duration,
+    pub text: String,
+}
+
+/// Reads the cues of an SRT or WebVTT file.
+pub fn read(path: &Path) -> Result<Vec<Cue>, PristineError> {
+    let text = std::fs::read_to_string(path)?;
+    Ok(parse(&text))
+}
+
+/// Parses the cues of SRT or WebVTT subtitles, dropping styling tags.
+///
+/// Both formats put the timings of a cue on a line of its own, followed by its text up to
+/// the next blank line, so cue numbers, headers and notes are simply skipped.
+pub fn parse(text: &str) -> Vec<Cue> {
+    let mut cues = vec![];
+    let mut lines = text.lines().map(|line| line.trim_end_matches('\r'));
+    while let Some(line) = lines.next() {
+        let timings = match line.find("-->") {
+            Some(arrow) => (
+                timestamp(&line[..arrow]),
+                // WebVTT may put cue settings after the end time.
+                line[arrow + 3..].split_whitespace().next().and_then(timestamp),
+            ),
+            None => continue,
+        };
+        let text = lines
+            .by_ref()
+            .take_while(|line| !line.trim().is_empty())
+            .collect::<Vec<_>>()
+            .join("\n");
+        if let (Some(start), Some(end)) = timings {
+            cues.extend(cue(start, end, &text));
+        }
+    }
+    cues
+}
+
+/// Makes a cue of styled text, `None` if nothing is left once the tags are stripped.
+pub(crate) fn cue(start: Duration, end: Duration, text: &str) -> Option<Cue> {
+    let text = text
+        .lines()
+        .map(strip_tags)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+    if text.is_empty() {
+        return None;
+    }
+    Some(Cue { start, end, text })
+}
+
+/// Writes cues as SRT.
+pub fn write_srt(cues: &[Cue], path: &Path) -> Result<(), PristineError> {
+    let srt = cues
+        .iter()
+        .enumerate()
+        .map(|(i, cue)| {
+            format!(
+                "{}\n{} --> {}\n{}\n",
+                i + 1,
+                srt_timestamp(cue.start),
+                srt_timestamp(cue.end),
+                cue.text
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    std::fs::write(path, srt)?;
+    Ok(())
+}
+
+/// Parses `hh:mm:ss,mmm` (SRT) or `[hh:]mm:ss.mmm` (WebVTT).
+fn timestamp(value: &str) -> Option<Duration> {
+    let mut seconds = 0.0;
+    for part in value.trim().replace(',', ".").split(':') {
+        seconds = seconds * 60.0 + part.parse::<f64>().ok()?;
+    }
+    if seconds < 0.0 || !seconds.is_finite() {
+        return None;
+    }
+    Some(Duration::from_secs_f64(seconds))
+}
+
+fn srt_timestamp(time: Duration) -> String {
+    let millis = time.as_millis();
+    format!(
+        "{:02}:{:02}:{:02},{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// Removes `<i>`, `<font …>`, `<c.class>` and similar tags from a line of text, and
+/// unescapes the entities of WebVTT and Pango markup.
+fn strip_tags(line: &str) -> String {
+    let mut text = String::with_capacity(line.len());
+    let mut in_tag = false;
+    for c in line.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => (),
+        }
+    }
+    unescape(text.trim())
+}
+
+/// Replaces the named and numeric character references in `text` by their characters,
+/// leaving unknown ones as they are.
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest.find(';').map(|end| (&rest[1..end], end));
+        let c = entity.and_then(|(name, _)| match name {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{a0}'),
+            _ => {
+                let code = match name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => name.strip_prefix('#').and_then(|dec| dec.parse().ok()),
+                };
+                code.and_then(std::char::from_u32)
+            }
+        });
+        match (c, entity) {
+            (Some(c), Some((_, end))) => {
+                unescaped.push(c);
+                rest = &rest[end + 1..];
+            }
+            _ => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}